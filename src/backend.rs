@@ -0,0 +1,439 @@
+use crate::error::{Error, Result};
+use crate::library::Library;
+use crate::sys::*;
+use std::ptr;
+
+#[cfg(unix)]
+const RUNTIME_NAMES: &[&str] = &["libvpl.so.2", "libmfx.so.1"];
+#[cfg(all(windows, target_pointer_width = "64"))]
+const RUNTIME_NAMES: &[&str] = &["libvpl.dll", "libmfxhw64.dll"];
+#[cfg(all(windows, target_pointer_width = "32"))]
+const RUNTIME_NAMES: &[&str] = &["libvpl.dll", "libmfxhw32.dll"];
+
+/// Media SDK entry points the session layer is built on.
+///
+/// Every method mirrors the C function of the same name and follows its
+/// contract: pointers are passed through unchanged, and surfaces and
+/// bitstreams handed to the asynchronous calls stay owned by the caller until
+/// the returned sync point has been synchronized.
+///
+/// [`SdkBackend`] calls into a dynamically loaded Media SDK runtime; [`MockBackend`] emulates
+/// it in-process for tests.
+///
+/// [`MockBackend`]: crate::MockBackend
+#[allow(clippy::missing_safety_doc)]
+pub trait Backend: Send + Sync {
+    unsafe fn init(
+        &self,
+        implementation: mfxIMPL,
+        ver: *const mfxVersion,
+        session: *mut *mut mfxSession,
+    ) -> mfxStatus;
+
+    unsafe fn close(&self, session: *mut mfxSession) -> mfxStatus;
+
+    unsafe fn query_impl(
+        &self,
+        session: *const mfxSession,
+        implementation: *mut mfxIMPL,
+    ) -> mfxStatus;
+
+    unsafe fn query_version(
+        &self,
+        session: *const mfxSession,
+        version: *mut mfxVersion,
+    ) -> mfxStatus;
+
+    unsafe fn sync_operation(
+        &self,
+        session: *const mfxSession,
+        syncp: mfxSyncPoint,
+        wait: mfxU32,
+    ) -> mfxStatus;
+
+    unsafe fn encode_query(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxVideoParam,
+        output: *mut mfxVideoParam,
+    ) -> mfxStatus;
+
+    unsafe fn encode_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus;
+
+    unsafe fn encode_init(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus;
+
+    unsafe fn encode_get_video_param(
+        &self,
+        session: *const mfxSession,
+        par: *mut mfxVideoParam,
+    ) -> mfxStatus;
+
+    unsafe fn encode_frame_async(
+        &self,
+        session: *const mfxSession,
+        ctrl: *const mfxEncodeCtrl,
+        surface: *const mfxFrameSurface1,
+        bs: *mut mfxBitstream,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus;
+
+    unsafe fn encode_reset(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus;
+
+    unsafe fn encode_close(&self, session: *const mfxSession) -> mfxStatus;
+
+    /// `request` points to two requests: VPP input and VPP output.
+    unsafe fn vpp_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus;
+
+    unsafe fn vpp_init(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus;
+
+    unsafe fn vpp_run_frame_async(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxFrameSurface1,
+        output: *mut mfxFrameSurface1,
+        aux: *const mfxExtVppAuxData,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus;
+
+    unsafe fn vpp_reset(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus;
+
+    unsafe fn vpp_close(&self, session: *const mfxSession) -> mfxStatus;
+
+    // Media SDK 1.x plugins. The defaults describe a oneVPL 2.x runtime,
+    // which has none.
+
+    unsafe fn user_load(
+        &self,
+        _session: *const mfxSession,
+        _uid: *const mfxPluginUID,
+        _version: mfxU32,
+    ) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+
+    unsafe fn user_unload(
+        &self,
+        _session: *const mfxSession,
+        _uid: *const mfxPluginUID,
+    ) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+
+    // oneVPL 2.x dispatcher. The defaults describe a Media SDK 1.x runtime,
+    // which only supports `init`.
+
+    /// Returns a null loader when the runtime has no dispatcher.
+    unsafe fn load(&self) -> mfxLoader {
+        ptr::null_mut()
+    }
+
+    unsafe fn unload(&self, _loader: mfxLoader) {}
+
+    unsafe fn create_config(&self, _loader: mfxLoader) -> mfxConfig {
+        ptr::null_mut()
+    }
+
+    unsafe fn set_config_filter_property(
+        &self,
+        _config: mfxConfig,
+        _name: *const mfxU8,
+        _value: mfxVariant,
+    ) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+
+    unsafe fn enum_implementations(
+        &self,
+        _loader: mfxLoader,
+        _i: mfxU32,
+        _format: mfxImplCapsDeliveryFormat,
+        _idesc: *mut mfxHDL,
+    ) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+
+    unsafe fn create_session(
+        &self,
+        _loader: mfxLoader,
+        _i: mfxU32,
+        _session: *mut *mut mfxSession,
+    ) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+
+    unsafe fn release_impl_description(&self, _loader: mfxLoader, _hdl: mfxHDL) -> mfxStatus {
+        MFX_ERR_UNSUPPORTED
+    }
+}
+
+/// Backend calling a Media SDK or oneVPL runtime loaded at runtime.
+pub struct SdkBackend {
+    functions: mfxFunctions,
+    // keeps the functions above mapped
+    _library: Library,
+}
+
+impl SdkBackend {
+    /// Loads the oneVPL dispatcher or, failing that, the Media SDK one:
+    /// `libvpl.so.2` or `libmfx.so.1` on Linux, `libvpl.dll` or
+    /// `libmfxhw64.dll` on Windows.
+    pub fn load() -> Result<SdkBackend> {
+        let mut errors = Vec::new();
+        for name in RUNTIME_NAMES {
+            match Library::open(name) {
+                Ok(library) => return SdkBackend::from_library(library),
+                Err(e) => errors.push(e),
+            }
+        }
+        Err(Error::RuntimeNotFound(errors.join("; ")))
+    }
+
+    /// Loads the runtime library at `path`.
+    pub fn open(path: &str) -> Result<SdkBackend> {
+        let library = Library::open(path).map_err(Error::RuntimeNotFound)?;
+        SdkBackend::from_library(library)
+    }
+
+    fn from_library(library: Library) -> Result<SdkBackend> {
+        let functions = unsafe { mfxFunctions::load(|name| library.symbol(name)) }
+            .map_err(Error::MissingSymbol)?;
+        Ok(SdkBackend {
+            functions,
+            _library: library,
+        })
+    }
+}
+
+impl Backend for SdkBackend {
+    unsafe fn init(
+        &self,
+        implementation: mfxIMPL,
+        ver: *const mfxVersion,
+        session: *mut *mut mfxSession,
+    ) -> mfxStatus {
+        (self.functions.MFXInit)(implementation, ver, session)
+    }
+
+    unsafe fn close(&self, session: *mut mfxSession) -> mfxStatus {
+        (self.functions.MFXClose)(session)
+    }
+
+    unsafe fn query_impl(
+        &self,
+        session: *const mfxSession,
+        implementation: *mut mfxIMPL,
+    ) -> mfxStatus {
+        (self.functions.MFXQueryIMPL)(session, implementation)
+    }
+
+    unsafe fn query_version(
+        &self,
+        session: *const mfxSession,
+        version: *mut mfxVersion,
+    ) -> mfxStatus {
+        (self.functions.MFXQueryVersion)(session, version)
+    }
+
+    unsafe fn sync_operation(
+        &self,
+        session: *const mfxSession,
+        syncp: mfxSyncPoint,
+        wait: mfxU32,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoCORE_SyncOperation)(session, syncp, wait)
+    }
+
+    unsafe fn encode_query(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxVideoParam,
+        output: *mut mfxVideoParam,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_Query)(session, input, output)
+    }
+
+    unsafe fn encode_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_QueryIOSurf)(session, par, request)
+    }
+
+    unsafe fn encode_init(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_Init)(session, par)
+    }
+
+    unsafe fn encode_get_video_param(
+        &self,
+        session: *const mfxSession,
+        par: *mut mfxVideoParam,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_GetVideoParam)(session, par)
+    }
+
+    unsafe fn encode_frame_async(
+        &self,
+        session: *const mfxSession,
+        ctrl: *const mfxEncodeCtrl,
+        surface: *const mfxFrameSurface1,
+        bs: *mut mfxBitstream,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_EncodeFrameAsync)(session, ctrl, surface, bs, syncp)
+    }
+
+    unsafe fn encode_reset(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_Reset)(session, par)
+    }
+
+    unsafe fn encode_close(&self, session: *const mfxSession) -> mfxStatus {
+        (self.functions.MFXVideoENCODE_Close)(session)
+    }
+
+    unsafe fn vpp_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoVPP_QueryIOSurf)(session, par, request)
+    }
+
+    unsafe fn vpp_init(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus {
+        (self.functions.MFXVideoVPP_Init)(session, par)
+    }
+
+    unsafe fn vpp_run_frame_async(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxFrameSurface1,
+        output: *mut mfxFrameSurface1,
+        aux: *const mfxExtVppAuxData,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus {
+        (self.functions.MFXVideoVPP_RunFrameVPPAsync)(session, input, output, aux, syncp)
+    }
+
+    unsafe fn vpp_reset(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus {
+        (self.functions.MFXVideoVPP_Reset)(session, par)
+    }
+
+    unsafe fn vpp_close(&self, session: *const mfxSession) -> mfxStatus {
+        (self.functions.MFXVideoVPP_Close)(session)
+    }
+
+    unsafe fn user_load(
+        &self,
+        session: *const mfxSession,
+        uid: *const mfxPluginUID,
+        version: mfxU32,
+    ) -> mfxStatus {
+        match self.functions.MFXVideoUSER_Load {
+            Some(f) => f(session, uid, version),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+
+    unsafe fn user_unload(
+        &self,
+        session: *const mfxSession,
+        uid: *const mfxPluginUID,
+    ) -> mfxStatus {
+        match self.functions.MFXVideoUSER_UnLoad {
+            Some(f) => f(session, uid),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+
+    unsafe fn load(&self) -> mfxLoader {
+        match self.functions.MFXLoad {
+            Some(f) => f(),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn unload(&self, loader: mfxLoader) {
+        if let Some(f) = self.functions.MFXUnload {
+            f(loader)
+        }
+    }
+
+    unsafe fn create_config(&self, loader: mfxLoader) -> mfxConfig {
+        match self.functions.MFXCreateConfig {
+            Some(f) => f(loader),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn set_config_filter_property(
+        &self,
+        config: mfxConfig,
+        name: *const mfxU8,
+        value: mfxVariant,
+    ) -> mfxStatus {
+        match self.functions.MFXSetConfigFilterProperty {
+            Some(f) => f(config, name, value),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+
+    unsafe fn enum_implementations(
+        &self,
+        loader: mfxLoader,
+        i: mfxU32,
+        format: mfxImplCapsDeliveryFormat,
+        idesc: *mut mfxHDL,
+    ) -> mfxStatus {
+        match self.functions.MFXEnumImplementations {
+            Some(f) => f(loader, i, format, idesc),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+
+    unsafe fn create_session(
+        &self,
+        loader: mfxLoader,
+        i: mfxU32,
+        session: *mut *mut mfxSession,
+    ) -> mfxStatus {
+        match self.functions.MFXCreateSession {
+            Some(f) => f(loader, i, session),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+
+    unsafe fn release_impl_description(&self, loader: mfxLoader, hdl: mfxHDL) -> mfxStatus {
+        match self.functions.MFXDispReleaseImplDescription {
+            Some(f) => f(loader, hdl),
+            None => MFX_ERR_UNSUPPORTED,
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::backend::{Backend, SdkBackend};
+use crate::error::{Error, MfxError, Result};
+use crate::session::{Loader, Session};
+use crate::sys::*;
+
+// probed from the largest down, the first accepted one is reported
+const SIZES: &[(mfxU16, mfxU16)] = &[
+    (16384, 16384),
+    (8192, 8192),
+    (8192, 4320),
+    (4096, 4096),
+    (4096, 2304),
+    (3840, 2160),
+    (2048, 2048),
+    (1920, 1088),
+];
+
+const PROBE_WIDTH: mfxU16 = 352;
+const PROBE_HEIGHT: mfxU16 = 288;
+const PROBE_KBPS: mfxU16 = 2000;
+const PROBE_QP: mfxU16 = 26;
+const PROBE_ICQ_QUALITY: mfxU16 = 23;
+const PROBE_JPEG_QUALITY: mfxU16 = 85;
+
+struct Codec {
+    id: mfxU32,
+    name: &'static str,
+    profiles: &'static [(mfxU16, &'static str)],
+}
+
+const CODECS: &[Codec] = &[
+    Codec {
+        id: MFX_CODEC_AVC,
+        name: "H.264",
+        profiles: &[
+            (MFX_PROFILE_AVC_CONSTRAINED_BASELINE, "Constrained Baseline"),
+            (MFX_PROFILE_AVC_BASELINE, "Baseline"),
+            (MFX_PROFILE_AVC_MAIN, "Main"),
+            (MFX_PROFILE_AVC_HIGH, "High"),
+            (MFX_PROFILE_AVC_HIGH10, "High 10"),
+        ],
+    },
+    Codec {
+        id: MFX_CODEC_HEVC,
+        name: "HEVC",
+        profiles: &[
+            (MFX_PROFILE_HEVC_MAIN, "Main"),
+            (MFX_PROFILE_HEVC_MAIN10, "Main 10"),
+            (MFX_PROFILE_HEVC_MAINSP, "Main Still Picture"),
+            (MFX_PROFILE_HEVC_REXT, "Range Extensions"),
+        ],
+    },
+    Codec {
+        id: MFX_CODEC_VP9,
+        name: "VP9",
+        profiles: &[
+            (MFX_PROFILE_VP9_0, "Profile 0"),
+            (MFX_PROFILE_VP9_1, "Profile 1"),
+            (MFX_PROFILE_VP9_2, "Profile 2"),
+            (MFX_PROFILE_VP9_3, "Profile 3"),
+        ],
+    },
+    Codec {
+        id: MFX_CODEC_AV1,
+        name: "AV1",
+        profiles: &[
+            (MFX_PROFILE_AV1_MAIN, "Main"),
+            (MFX_PROFILE_AV1_HIGH, "High"),
+            (MFX_PROFILE_AV1_PRO, "Professional"),
+        ],
+    },
+    Codec {
+        id: MFX_CODEC_JPEG,
+        name: "JPEG",
+        profiles: &[(MFX_PROFILE_JPEG_BASELINE, "Baseline")],
+    },
+];
+
+const RATE_CONTROL_METHODS: &[(mfxU16, &str)] = &[
+    (MFX_RATECONTROL_CBR, "CBR"),
+    (MFX_RATECONTROL_VBR, "VBR"),
+    (MFX_RATECONTROL_CQP, "CQP"),
+    (MFX_RATECONTROL_AVBR, "AVBR"),
+    (MFX_RATECONTROL_LA, "LA"),
+    (MFX_RATECONTROL_ICQ, "ICQ"),
+    (MFX_RATECONTROL_VCM, "VCM"),
+    (MFX_RATECONTROL_LA_ICQ, "LA_ICQ"),
+    (MFX_RATECONTROL_LA_HRD, "LA_HRD"),
+    (MFX_RATECONTROL_QVBR, "QVBR"),
+];
+
+// FourCC, name, chroma format, bit depth, shift
+const INPUT_FORMATS: &[(mfxU32, &str, mfxU16, mfxU16, mfxU16)] = &[
+    (MFX_FOURCC_NV12, "NV12", MFX_CHROMAFORMAT_YUV420, 8, 0),
+    (MFX_FOURCC_P010, "P010", MFX_CHROMAFORMAT_YUV420, 10, 1),
+    (MFX_FOURCC_YUY2, "YUY2", MFX_CHROMAFORMAT_YUV422, 8, 0),
+    (MFX_FOURCC_Y210, "Y210", MFX_CHROMAFORMAT_YUV422, 10, 1),
+    (MFX_FOURCC_AYUV, "AYUV", MFX_CHROMAFORMAT_YUV444, 8, 0),
+    (MFX_FOURCC_Y410, "Y410", MFX_CHROMAFORMAT_YUV444, 10, 0),
+    (MFX_FOURCC_RGB4, "RGB4", MFX_CHROMAFORMAT_YUV444, 8, 0),
+];
+
+/// What one implementation, i.e. one adapter of a runtime, can do.
+#[derive(Debug, Clone)]
+pub struct ImplementationCaps {
+    /// Name reported by the oneVPL dispatcher, or the `MFXInit`
+    /// implementation the session was opened with on Media SDK runtimes.
+    pub name: String,
+    /// `MFX_IMPL_*` value reported by `MFXQueryIMPL`.
+    pub implementation: i32,
+    /// API version reported by `MFXQueryVersion`, as (major, minor).
+    pub api_version: (u16, u16),
+    /// One entry per supported codec.
+    pub encoders: Vec<EncoderCaps>,
+}
+
+/// Encoder capabilities for one codec, as accepted by
+/// `MFXVideoENCODE_Query`.
+#[derive(Debug, Clone)]
+pub struct EncoderCaps {
+    pub codec: &'static str,
+    pub profiles: Vec<&'static str>,
+    /// Largest frame size accepted, 0x0 if none of the probed ones is.
+    pub max_width: u16,
+    pub max_height: u16,
+    pub rate_control_methods: Vec<&'static str>,
+    pub low_power: bool,
+    pub input_fourccs: Vec<&'static str>,
+}
+
+/// Loads the runtime and queries the capabilities of every implementation
+/// it offers.
+pub fn query_capabilities() -> Result<Vec<ImplementationCaps>> {
+    query_capabilities_with_backend(Arc::new(SdkBackend::load()?))
+}
+
+/// Same as [`query_capabilities`], routing every Media SDK call through
+/// `backend`.
+///
+/// oneVPL runtimes report every implementation their dispatcher enumerates,
+/// skipping those that fail to open a session or answer the queries. Media
+/// SDK runtimes are probed with `MFXInit` on each of the four hardware
+/// adapters and the software implementation, skipping those that fail.
+pub fn query_capabilities_with_backend(
+    backend: Arc<dyn Backend>,
+) -> Result<Vec<ImplementationCaps>> {
+    let mut implementations = Vec::new();
+    match Loader::new(&backend) {
+        Some(loader) => {
+            let loader = Rc::new(loader);
+            for index in 0.. {
+                let name = match loader.impl_name(index) {
+                    Ok(name) => name,
+                    Err(ref e) if e.mfx_error() == Some(MfxError::NotFound) => break,
+                    Err(e) => return Err(e),
+                };
+                let session = Session::create(backend.clone(), loader.clone(), index);
+                if let Ok(caps) = session.and_then(|session| query_session(&session, &name)) {
+                    implementations.push(caps);
+                }
+            }
+        }
+        None => {
+            let adapters = [
+                (MFX_IMPL_HARDWARE, "hardware"),
+                (MFX_IMPL_HARDWARE2, "hardware (adapter 2)"),
+                (MFX_IMPL_HARDWARE3, "hardware (adapter 3)"),
+                (MFX_IMPL_HARDWARE4, "hardware (adapter 4)"),
+                (MFX_IMPL_SOFTWARE, "software"),
+            ];
+            for &(implementation, name) in &adapters {
+                let version = mfxVersion::new(1, 0);
+                if let Ok(mut session) = Session::init(backend.clone(), implementation, &version) {
+                    for codec in CODECS {
+                        session.load_encoder_plugin(codec.id);
+                    }
+                    implementations.push(query_session(&session, name)?);
+                }
+            }
+        }
+    }
+    Ok(implementations)
+}
+
+fn query_session(session: &Session, name: &str) -> Result<ImplementationCaps> {
+    let version = session.query_version()?;
+    let mut encoders = Vec::new();
+    for codec in CODECS {
+        if let Some(caps) = query_encoder(session, codec)? {
+            encoders.push(caps);
+        }
+    }
+    Ok(ImplementationCaps {
+        name: name.to_string(),
+        implementation: session.query_impl()?,
+        api_version: (version.Major, version.Minor),
+        encoders,
+    })
+}
+
+fn query_encoder(session: &Session, codec: &Codec) -> Result<Option<EncoderCaps>> {
+    let mut base = probe_param(codec.id);
+    if query(session, &base)?.is_none() {
+        // some encoders only exist in LowPower (VDENC) mode
+        mfx_mut(&mut base).LowPower = MFX_CODINGOPTION_ON;
+        if query(session, &base)?.is_none() {
+            return Ok(None);
+        }
+    }
+
+    let mut profiles = Vec::new();
+    for &(profile, name) in codec.profiles {
+        let mut par = base;
+        mfx_mut(&mut par).CodecProfile = profile;
+        if let Some(out) = query(session, &par)? {
+            if mfx(&out).CodecProfile == profile {
+                profiles.push(name);
+            }
+        }
+    }
+
+    let (mut max_width, mut max_height) = (0, 0);
+    for &(width, height) in SIZES {
+        let mut par = base;
+        set_size(&mut mfx_mut(&mut par).FrameInfo, width, height);
+        if let Some(out) = query(session, &par)? {
+            let info = mfx(&out).FrameInfo;
+            if info.Width == width && info.Height == height {
+                max_width = width;
+                max_height = height;
+                break;
+            }
+        }
+    }
+
+    let mut rate_control_methods = Vec::new();
+    if codec.id != MFX_CODEC_JPEG {
+        for &(method, name) in RATE_CONTROL_METHODS {
+            let mut par = base;
+            set_rate_control(mfx_mut(&mut par), method);
+            if let Some(out) = query(session, &par)? {
+                if mfx(&out).RateControlMethod == method {
+                    rate_control_methods.push(name);
+                }
+            }
+        }
+    }
+
+    let mut par = base;
+    mfx_mut(&mut par).LowPower = MFX_CODINGOPTION_ON;
+    let low_power = match query(session, &par)? {
+        Some(out) => mfx(&out).LowPower == MFX_CODINGOPTION_ON,
+        None => false,
+    };
+
+    let mut input_fourccs = Vec::new();
+    for &(fourcc, name, chroma_format, bit_depth, shift) in INPUT_FORMATS {
+        let mut par = base;
+        let info = &mut mfx_mut(&mut par).FrameInfo;
+        info.FourCC = fourcc;
+        info.ChromaFormat = chroma_format;
+        info.BitDepthLuma = bit_depth;
+        info.BitDepthChroma = bit_depth;
+        info.Shift = shift;
+        if let Some(out) = query(session, &par)? {
+            if mfx(&out).FrameInfo.FourCC == fourcc {
+                input_fourccs.push(name);
+            }
+        }
+    }
+
+    Ok(Some(EncoderCaps {
+        codec: codec.name,
+        profiles,
+        max_width,
+        max_height,
+        rate_control_methods,
+        low_power,
+        input_fourccs,
+    }))
+}
+
+/// Smallest parameter set the runtime should accept for `codec`.
+fn probe_param(codec: mfxU32) -> mfxVideoParam {
+    let mut par = mfxVideoParam::new();
+    par.IOPattern = MFX_IOPATTERN_IN_SYSTEM_MEMORY;
+    if codec == MFX_CODEC_JPEG {
+        // in place of the target usage and rate control
+        par.u.jpeg.Interleaved = MFX_SCANTYPE_INTERLEAVED;
+        par.u.jpeg.Quality = PROBE_JPEG_QUALITY;
+    } else {
+        let mfx = mfx_mut(&mut par);
+        mfx.TargetUsage = MFX_TARGETUSAGE_BALANCED;
+        set_rate_control(mfx, MFX_RATECONTROL_VBR);
+    }
+    let mfx = mfx_mut(&mut par);
+    mfx.CodecId = codec;
+    let info = &mut mfx.FrameInfo;
+    info.FourCC = MFX_FOURCC_NV12;
+    info.ChromaFormat = MFX_CHROMAFORMAT_YUV420;
+    info.PicStruct = MFX_PICSTRUCT_PROGRESSIVE;
+    info.FrameRateExtN = 30;
+    info.FrameRateExtD = 1;
+    set_size(info, PROBE_WIDTH, PROBE_HEIGHT);
+    par
+}
+
+fn set_size(info: &mut mfxFrameInfo, width: mfxU16, height: mfxU16) {
+    info.Width = width;
+    info.Height = height;
+    info.CropW = width;
+    info.CropH = height;
+}
+
+fn set_rate_control(mfx: &mut mfxInfoMFX, method: mfxU16) {
+    mfx.RateControlMethod = method;
+    match method {
+        MFX_RATECONTROL_CQP => {
+            mfx.u1.QPI = PROBE_QP;
+            mfx.u2.QPP = PROBE_QP;
+            mfx.u3.QPB = PROBE_QP;
+        }
+        MFX_RATECONTROL_ICQ | MFX_RATECONTROL_LA_ICQ => mfx.u2.ICQQuality = PROBE_ICQ_QUALITY,
+        _ => {
+            mfx.u2.TargetKbps = PROBE_KBPS;
+            mfx.u3.MaxKbps = PROBE_KBPS;
+        }
+    }
+}
+
+fn mfx(par: &mfxVideoParam) -> &mfxInfoMFX {
+    // every parameter set built here is an ENCODE one
+    unsafe { &par.u.mfx }
+}
+
+fn mfx_mut(par: &mut mfxVideoParam) -> &mut mfxInfoMFX {
+    unsafe { &mut par.u.mfx }
+}
+
+/// Runtime's corrected copy of `par`, `None` if it is rejected.
+fn query(session: &Session, par: &mfxVideoParam) -> Result<Option<mfxVideoParam>> {
+    let mut out = *par;
+    match session.encode_query(&mut out) {
+        Ok(_) => Ok(Some(out)),
+        Err(Error::Mfx {
+            error: MfxError::Unsupported,
+            ..
+        })
+        | Err(Error::Mfx {
+            error: MfxError::InvalidVideoParam,
+            ..
+        })
+        | Err(Error::Mfx {
+            error: MfxError::IncompatibleVideoParam,
+            ..
+        }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,1900 @@
+#![allow(non_snake_case)]
+
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::backend::{Backend, SdkBackend};
+use crate::error::{Error, MfxError, MfxWarning, Result};
+use crate::level::{Level, LevelRequirements};
+use crate::options::{CodingOption, CodingOption2, CodingOption3, WeightedPrediction};
+use crate::session::Session;
+use crate::surface::{SurfaceLease, SurfacePool};
+use crate::sys::*;
+
+const SYNC_TIMEOUT: mfxU32 = 6000;
+const DEVICE_BUSY_WAIT: Duration = Duration::from_millis(1);
+// Media SDK timestamps are in units of a 90 kHz clock
+const TIME_STAMP_FREQUENCY: u64 = 90000;
+// largest frame the 16-bit frame info fields hold once aligned
+const MAX_SIZE: usize = 16384;
+// MAX_TILE_COLS and MAX_TILE_ROWS of the AV1 specification
+const AV1_MAX_TILES: u16 = 64;
+// VP9 has up to 64 tile columns, a power of two, and up to 4 tile rows
+const VP9_MAX_TILE_COLUMNS: u16 = 64;
+const VP9_MAX_TILE_ROWS: u16 = 4;
+const VP9_MAX_SEGMENTS: usize = 8;
+// GopRefDist of at most 16
+const MAX_B_FRAMES: u16 = 15;
+// JPEG output bound, as in the Media SDK samples
+const JPEG_BYTES_PER_PIXEL: usize = 4;
+// H.264 and HEVC quantizer and quality range, VP9 and AV1 quantizer index
+const MAX_QP: u16 = 51;
+const MAX_QINDEX: u16 = 255;
+const LOOK_AHEAD_DEPTHS: RangeInclusive<u16> = 10..=100;
+// largest value a 16-bit field and BRCParamMultiplier hold together
+const MAX_BRC_VALUE: u32 = 0xffff * 0x10000 - 1;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+// defaults of the rate control methods parsed by name
+const DEFAULT_QP: u16 = 26;
+const DEFAULT_QUALITY: u16 = 23;
+// 1% after 1000 frames
+const DEFAULT_AVBR_ACCURACY: u16 = 10;
+const DEFAULT_AVBR_CONVERGENCE: u16 = 10;
+
+/// Frame rate as a fraction of frames per second, e.g. 30000/1001 for
+/// 29.97 fps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u32,
+    pub den: u32,
+}
+
+/// Output codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// H.264 / AVC.
+    Avc,
+    /// H.265 / HEVC.
+    Hevc,
+    /// VP9, usually stored in IVF or WebM.
+    Vp9,
+    /// AV1. Having no Annex B byte stream, it is usually stored in IVF.
+    Av1,
+    /// Baseline JPEG, one image per frame, or Motion JPEG when concatenated.
+    Jpeg,
+}
+
+/// Codec profile. Not every profile exists for every codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// AVC Baseline.
+    Baseline,
+    /// AVC Constrained Baseline, the subset of Baseline and Main most
+    /// decoders support.
+    ConstrainedBaseline,
+    /// AVC, HEVC or AV1 Main, VP9 profile 0.
+    Main,
+    /// AVC High.
+    High,
+    /// AVC High 10, encoded from P010 surfaces.
+    High10,
+    /// HEVC Main 10, VP9 profile 2 or 10-bit AV1 Main, encoded from P010
+    /// surfaces.
+    Main10,
+    /// HEVC Main 4:4:4 (range extensions), VP9 profile 1 or AV1 High,
+    /// encoded from AYUV surfaces.
+    Main444,
+}
+
+/// Bitrate control method. Quantizers and qualities go from 1 (best) to 51,
+/// or to 255 for VP9 and AV1 quantizers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant bitrate.
+    Cbr,
+    /// Variable bitrate.
+    Vbr,
+    /// Constant quantizer for I, P and B frames, ignoring the bitrate.
+    Cqp { qpi: u16, qpp: u16, qpb: u16 },
+    /// Average variable bitrate, H.264 only. The bitrate is reached within
+    /// `accuracy` tenths of a percent after `convergence` hundreds of frames.
+    Avbr { accuracy: u16, convergence: u16 },
+    /// Intelligent constant quality, ignoring the bitrate. H.264 or HEVC.
+    Icq { quality: u16 },
+    /// Variable bitrate with look-ahead over `depth` frames, 10 to 100 or 0
+    /// for the runtime default. H.264 only.
+    La { depth: u16 },
+    /// Intelligent constant quality with look-ahead, ignoring the bitrate.
+    /// H.264 only.
+    LaIcq { quality: u16, depth: u16 },
+    /// Quality-defined variable bitrate, capped by the bitrate. H.264 or
+    /// HEVC.
+    Qvbr { quality: u16 },
+}
+
+/// Distance between I-frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyframeInterval {
+    Frames(u16),
+    /// Rounded to the nearest whole number of frames at the configured
+    /// frame rate.
+    Time(Duration),
+}
+
+/// Layout of the raw YUV 4:2:0 input frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Planar Y, U, V.
+    I420,
+    /// Planar Y followed by interleaved U and V.
+    Nv12,
+}
+
+/// Quantizer and loop filter adjustments of one VP9 segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Vp9Segment {
+    /// Added to the frame quantizer index, -255 to 255.
+    pub qindex_delta: i16,
+    /// Added to the frame loop filter level, -63 to 63.
+    pub loop_filter_level_delta: i16,
+}
+
+/// VP9 segmentation map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vp9Segmentation {
+    /// One to eight segments.
+    pub segments: Vec<Vp9Segment>,
+    /// Side in pixels of the blocks `segment_ids` covers: 8, 16, 32 or 64.
+    pub block_size: u16,
+    /// Index into `segments` of each block, in raster order, over the frame
+    /// padded to a multiple of 16 pixels as the encoder sees it.
+    pub segment_ids: Vec<u8>,
+}
+
+/// Encoder configuration.
+#[derive(Debug, Clone)]
+pub struct EncoderConfig {
+    pub width: usize,
+    pub height: usize,
+    /// Target bitrate in kbps, unused by CQP, ICQ and LA_ICQ.
+    pub bitrate: u32,
+    /// VBR and QVBR peak bitrate in kbps, 0 for the runtime default.
+    pub max_bitrate: u32,
+    /// HRD buffer size in KB, 0 for the runtime default.
+    pub buffer_size: u32,
+    /// HRD buffer fullness in KB before the first frame is removed, 0 for
+    /// the runtime default. Only used by CBR, VBR, LA and QVBR.
+    pub initial_delay: u32,
+    /// Sets the encoder timing, written to the SPS VUI by the runtime, and
+    /// the packet timestamps.
+    pub frame_rate: FrameRate,
+    pub codec: Codec,
+    /// `None` lets the runtime choose.
+    pub profile: Option<Profile>,
+    /// H.264 level the stream must fit in, `None` for the lowest one it
+    /// does.
+    pub level: Option<Level>,
+    pub rate_control: RateControl,
+    /// 1 (best quality) to 7 (best speed).
+    pub target_usage: u16,
+    /// Distance between I-frames, `None` for the runtime default.
+    pub keyframe_interval: Option<KeyframeInterval>,
+    /// B-frames between reference frames, `None` for the runtime default.
+    /// H.264 and HEVC only.
+    pub b_frames: Option<u16>,
+    /// I-frames per IDR frame, 1 making every I-frame an IDR frame. `None`
+    /// leaves the runtime default: every I-frame for H.264, only the first
+    /// for HEVC. H.264 and HEVC only.
+    pub idr_interval: Option<u16>,
+    /// No frame refers across an I-frame, so the stream can be cut at any
+    /// of them.
+    pub closed_gop: bool,
+    /// Keeps the GOP structure fixed, without I-frames inserted on scene
+    /// changes.
+    pub strict_gop: bool,
+    /// Entropy coding, AUD and SEI options. H.264 and HEVC only.
+    pub coding_option: CodingOption,
+    /// Frame size limits, trellis and adaptive GOP options. H.264 and HEVC
+    /// only.
+    pub coding_option2: CodingOption2,
+    /// Weighted prediction and low delay options. H.264 and HEVC only.
+    pub coding_option3: CodingOption3,
+    pub input_format: InputFormat,
+    /// Number of frames the runtime may process in parallel, and the
+    /// encoder keeps in flight before waiting for the oldest. 0 for the
+    /// runtime default.
+    pub async_depth: u16,
+    /// AV1 or VP9 tile columns, 0 for the runtime default.
+    pub tile_columns: u16,
+    /// AV1 or VP9 tile rows, 0 for the runtime default.
+    pub tile_rows: u16,
+    pub vp9_segmentation: Option<Vp9Segmentation>,
+    /// JPEG quality, 1 (smallest) to 100 (best). Takes the place of the
+    /// bitrate, rate control, target usage and GOP settings.
+    pub jpeg_quality: u16,
+}
+
+/// Encoded access unit.
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub data: Vec<u8>,
+    /// Presentation time in 90 kHz units, derived from the frame rate.
+    pub timestamp: u64,
+    pub frame_type: u16,
+}
+
+/// Per-frame overrides, passed with [`Encoder::encode_with_control`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameControl {
+    /// Codes the frame as an I-frame or IDR frame, for instance when a
+    /// receiver lost pictures.
+    pub force: Option<ForcedFrame>,
+    /// Quantizer of the frame. CQP only.
+    pub qp: Option<u16>,
+    /// Skips the frame the way [`CodingOption2::skip_frame`] says, which
+    /// must be set. H.264 and HEVC only.
+    pub skip: bool,
+}
+
+/// Frame type [`FrameControl::force`] imposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForcedFrame {
+    I,
+    /// An I-frame nothing after it refers across: an IDR frame for H.264
+    /// and HEVC, a key frame for VP9 and AV1.
+    Idr,
+}
+
+/// Outcome of [`Encoder::reconfigure`].
+#[derive(Debug, Clone)]
+pub struct Reconfiguration {
+    /// Whether the runtime applied the change to the running encoder. When
+    /// it could not, the encoder was closed and initialized again on the
+    /// same session and surfaces, starting a new sequence.
+    pub in_place: bool,
+}
+
+/// Bitrates and HRD buffer sizes in effect, as the runtime reports them. The
+/// ones the rate control method does not use are 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BitrateParams {
+    pub target_kbps: u32,
+    pub max_kbps: u32,
+    pub buffer_size_kb: u32,
+    pub initial_delay_kb: u32,
+}
+
+/// H.264, HEVC, VP9, AV1 or JPEG encoder taking YUV 4:2:0 frames through VPP into ENCODE.
+pub struct Encoder {
+    // declared first so the session is closed before the surfaces are freed
+    session: Session,
+    config: EncoderConfig,
+
+    vpp_pool_in: SurfacePool,
+    // VPP output surfaces, which ENCODE takes as they are
+    shared_pool: SurfacePool,
+    // control of the frame in each shared surface the pool may grow to,
+    // read by the runtime until the surface is unlocked
+    enc_ctrls: Vec<mfxEncodeCtrl>,
+
+    // what VPP and ENCODE asked for at init, which the surfaces were
+    // allocated for
+    vpp_requests: [mfxFrameAllocRequest; 2],
+    enc_request: mfxFrameAllocRequest,
+
+    // frames in flight in submission order, at most pipeline_depth of them,
+    // and the bitstreams free for the next ones
+    pending: VecDeque<PendingFrame>,
+    bitstreams: Vec<Bitstream>,
+    bitstream_size: usize,
+    pipeline_depth: usize,
+
+    // timestamp of the first frame at the current frame rate, and the
+    // frames submitted since, numbering the next one
+    timestamp_base: u64,
+    frame_count: u64,
+
+    state: StreamState,
+}
+
+// Whether frames may be buffered in VPP and ENCODE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamState {
+    Encoding,
+    // nothing is buffered, after init or a flush
+    Drained,
+    // a reconfiguration failed partway, which may have left ENCODE closed
+    // or VPP on parameters the encoder does not know
+    Broken,
+}
+
+// Frame submitted to the encoder and not synchronized yet.
+struct PendingFrame {
+    syncp: mfxSyncPoint,
+    bitstream: Bitstream,
+}
+
+// Bitstream receiving one encoded frame. The header is boxed so the pointer
+// the runtime holds stays valid while the frame is in flight.
+struct Bitstream {
+    bs: Box<mfxBitstream>,
+    _buffer: Vec<u8>,
+}
+
+impl Bitstream {
+    fn new(size: usize) -> Bitstream {
+        let buffer: Vec<u8> = vec![0; size];
+        let mut bs = Box::new(mfxBitstream::new());
+        bs.MaxLength = size as u32;
+        bs.Data = buffer.as_ptr();
+        Bitstream {
+            bs,
+            _buffer: buffer,
+        }
+    }
+}
+
+impl FrameRate {
+    pub fn new(num: u32, den: u32) -> FrameRate {
+        FrameRate { num, den }
+    }
+
+    // Presentation time of the `frame`th frame on the 90 kHz clock, computed
+    // in 128 bits as large unreduced fractions would overflow 64.
+    fn timestamp(self, frame: u64) -> u64 {
+        let ticks = frame as u128 * TIME_STAMP_FREQUENCY as u128 * self.den as u128;
+        (ticks / self.num as u128) as u64
+    }
+}
+
+/// Parses `num/den` or a whole number of frames per second.
+impl FromStr for FrameRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FrameRate> {
+        let invalid = || Error::Config(format!("invalid frame rate '{}'", s));
+        let (num, den) = match s.find('/') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, "1"),
+        };
+        Ok(FrameRate {
+            num: num.parse().map_err(|_| invalid())?,
+            den: den.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Codec {
+    fn id(self) -> mfxU32 {
+        match self {
+            Codec::Avc => MFX_CODEC_AVC,
+            Codec::Hevc => MFX_CODEC_HEVC,
+            Codec::Vp9 => MFX_CODEC_VP9,
+            Codec::Av1 => MFX_CODEC_AV1,
+            Codec::Jpeg => MFX_CODEC_JPEG,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Codec> {
+        match s {
+            "h264" | "avc" => Ok(Codec::Avc),
+            "h265" | "hevc" => Ok(Codec::Hevc),
+            "vp9" => Ok(Codec::Vp9),
+            "av1" => Ok(Codec::Av1),
+            "jpeg" | "mjpeg" => Ok(Codec::Jpeg),
+            _ => Err(Error::Config(format!("unknown codec '{}'", s))),
+        }
+    }
+}
+
+impl Profile {
+    // CodecProfile value, None if the profile does not exist for `codec`.
+    fn value(self, codec: Codec) -> Option<mfxU16> {
+        match (codec, self) {
+            (Codec::Avc, Profile::Baseline) => Some(MFX_PROFILE_AVC_BASELINE),
+            (Codec::Avc, Profile::ConstrainedBaseline) => {
+                Some(MFX_PROFILE_AVC_CONSTRAINED_BASELINE)
+            }
+            (Codec::Avc, Profile::Main) => Some(MFX_PROFILE_AVC_MAIN),
+            (Codec::Avc, Profile::High) => Some(MFX_PROFILE_AVC_HIGH),
+            (Codec::Avc, Profile::High10) => Some(MFX_PROFILE_AVC_HIGH10),
+            (Codec::Hevc, Profile::Main) => Some(MFX_PROFILE_HEVC_MAIN),
+            (Codec::Hevc, Profile::Main10) => Some(MFX_PROFILE_HEVC_MAIN10),
+            (Codec::Hevc, Profile::Main444) => Some(MFX_PROFILE_HEVC_REXT),
+            (Codec::Vp9, Profile::Main) => Some(MFX_PROFILE_VP9_0),
+            (Codec::Vp9, Profile::Main10) => Some(MFX_PROFILE_VP9_2),
+            (Codec::Vp9, Profile::Main444) => Some(MFX_PROFILE_VP9_1),
+            (Codec::Av1, Profile::Main) | (Codec::Av1, Profile::Main10) => {
+                Some(MFX_PROFILE_AV1_MAIN)
+            }
+            (Codec::Av1, Profile::Main444) => Some(MFX_PROFILE_AV1_HIGH),
+            _ => None,
+        }
+    }
+
+    // FourCC, chroma format, bit depth and shift of the encoder surfaces.
+    fn surface_format(profile: Option<Profile>) -> (mfxU32, mfxU16, mfxU16, mfxU16) {
+        match profile {
+            Some(Profile::Main10) | Some(Profile::High10) => {
+                (MFX_FOURCC_P010, MFX_CHROMAFORMAT_YUV420, 10, 1)
+            }
+            Some(Profile::Main444) => (MFX_FOURCC_AYUV, MFX_CHROMAFORMAT_YUV444, 8, 0),
+            _ => (MFX_FOURCC_NV12, MFX_CHROMAFORMAT_YUV420, 8, 0),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Profile> {
+        match s {
+            "baseline" => Ok(Profile::Baseline),
+            "constrained-baseline" => Ok(Profile::ConstrainedBaseline),
+            "main" => Ok(Profile::Main),
+            "high" => Ok(Profile::High),
+            "high10" => Ok(Profile::High10),
+            "main10" => Ok(Profile::Main10),
+            "main444" => Ok(Profile::Main444),
+            _ => Err(Error::Config(format!("unknown profile '{}'", s))),
+        }
+    }
+}
+
+impl RateControl {
+    fn method(self) -> mfxU16 {
+        match self {
+            RateControl::Cbr => MFX_RATECONTROL_CBR,
+            RateControl::Vbr => MFX_RATECONTROL_VBR,
+            RateControl::Cqp { .. } => MFX_RATECONTROL_CQP,
+            RateControl::Avbr { .. } => MFX_RATECONTROL_AVBR,
+            RateControl::Icq { .. } => MFX_RATECONTROL_ICQ,
+            RateControl::La { .. } => MFX_RATECONTROL_LA,
+            RateControl::LaIcq { .. } => MFX_RATECONTROL_LA_ICQ,
+            RateControl::Qvbr { .. } => MFX_RATECONTROL_QVBR,
+        }
+    }
+
+    fn uses_bitrate(self) -> bool {
+        !matches!(
+            self,
+            RateControl::Cqp { .. } | RateControl::Icq { .. } | RateControl::LaIcq { .. }
+        )
+    }
+
+    fn uses_max_bitrate(self) -> bool {
+        matches!(self, RateControl::Vbr | RateControl::Qvbr { .. })
+    }
+
+    // methods with an HRD model, starting from the initial delay
+    fn uses_initial_delay(self) -> bool {
+        matches!(
+            self,
+            RateControl::Cbr | RateControl::Vbr | RateControl::La { .. } | RateControl::Qvbr { .. }
+        )
+    }
+
+    // Fills the union members of `method()`, with the bitrates and buffer
+    // sizes of `config` divided by BRCParamMultiplier until they fit 16 bits.
+    fn set(self, mfx: &mut mfxInfoMFX, config: &EncoderConfig) {
+        let bitrate = if self.uses_bitrate() {
+            config.bitrate
+        } else {
+            0
+        };
+        let max_bitrate = if self.uses_max_bitrate() {
+            config.max_bitrate
+        } else {
+            0
+        };
+        let initial_delay = if self.uses_initial_delay() {
+            config.initial_delay
+        } else {
+            0
+        };
+        let largest = bitrate
+            .max(max_bitrate)
+            .max(initial_delay)
+            .max(config.buffer_size);
+        let multiplier = largest / 0x10000 + 1;
+        let scale = |value: u32| (value / multiplier) as mfxU16;
+        mfx.BRCParamMultiplier = multiplier as mfxU16;
+        mfx.BufferSizeInKB = scale(config.buffer_size);
+
+        let bitrate = scale(bitrate);
+        mfx.RateControlMethod = self.method();
+        match self {
+            RateControl::Cqp { qpi, qpp, qpb } => {
+                mfx.u1.QPI = qpi;
+                mfx.u2.QPP = qpp;
+                mfx.u3.QPB = qpb;
+            }
+            RateControl::Avbr {
+                accuracy,
+                convergence,
+            } => {
+                mfx.u1.Accuracy = accuracy;
+                mfx.u2.TargetKbps = bitrate;
+                mfx.u3.Convergence = convergence;
+            }
+            RateControl::Icq { quality } | RateControl::LaIcq { quality, .. } => {
+                mfx.u2.ICQQuality = quality;
+            }
+            _ => {
+                mfx.u1.InitialDelayInKB = scale(initial_delay);
+                mfx.u2.TargetKbps = bitrate;
+                mfx.u3.MaxKbps = scale(max_bitrate);
+            }
+        }
+    }
+
+    fn validate(self, codec: Codec) -> Result<()> {
+        let name = match self {
+            RateControl::Cbr => "CBR",
+            RateControl::Vbr => "VBR",
+            RateControl::Cqp { .. } => "CQP",
+            RateControl::Avbr { .. } => "AVBR",
+            RateControl::Icq { .. } => "ICQ",
+            RateControl::La { .. } => "LA",
+            RateControl::LaIcq { .. } => "LA_ICQ",
+            RateControl::Qvbr { .. } => "QVBR",
+        };
+        let supported = match self {
+            RateControl::Avbr { .. } | RateControl::La { .. } | RateControl::LaIcq { .. } => {
+                codec == Codec::Avc
+            }
+            RateControl::Icq { .. } | RateControl::Qvbr { .. } => {
+                matches!(codec, Codec::Avc | Codec::Hevc)
+            }
+            _ => true,
+        };
+        if !supported {
+            return Err(Error::Config(format!(
+                "{} rate control is not supported for {:?}",
+                name, codec
+            )));
+        }
+        match self {
+            RateControl::Cqp { qpi, qpp, qpb } => {
+                let max_qp = match codec {
+                    Codec::Vp9 | Codec::Av1 => MAX_QINDEX,
+                    _ => MAX_QP,
+                };
+                if [qpi, qpp, qpb].iter().any(|&qp| qp == 0 || qp > max_qp) {
+                    return Err(Error::Config(format!(
+                        "{:?} quantizers must be between 1 and {}",
+                        codec, max_qp
+                    )));
+                }
+            }
+            RateControl::Avbr {
+                accuracy,
+                convergence,
+            } if accuracy == 0 || convergence == 0 => {
+                return Err(Error::Config(
+                    "AVBR accuracy and convergence must be non-zero".to_string(),
+                ));
+            }
+            RateControl::Icq { quality }
+            | RateControl::LaIcq { quality, .. }
+            | RateControl::Qvbr { quality }
+                if quality == 0 || quality > MAX_QP =>
+            {
+                return Err(Error::Config(format!(
+                    "{} quality must be between 1 and {}",
+                    name, MAX_QP
+                )));
+            }
+            _ => {}
+        }
+        if let RateControl::La { depth } | RateControl::LaIcq { depth, .. } = self {
+            if depth != 0 && !LOOK_AHEAD_DEPTHS.contains(&depth) {
+                return Err(Error::Config(format!(
+                    "look-ahead depth must be between {} and {}",
+                    LOOK_AHEAD_DEPTHS.start(),
+                    LOOK_AHEAD_DEPTHS.end()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the method name, with the quantizers, qualities and accuracy left
+/// at their defaults, and the look-ahead depth to the runtime.
+impl FromStr for RateControl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RateControl> {
+        match s {
+            "cbr" => Ok(RateControl::Cbr),
+            "vbr" => Ok(RateControl::Vbr),
+            "cqp" => Ok(RateControl::Cqp {
+                qpi: DEFAULT_QP,
+                qpp: DEFAULT_QP,
+                qpb: DEFAULT_QP,
+            }),
+            "avbr" => Ok(RateControl::Avbr {
+                accuracy: DEFAULT_AVBR_ACCURACY,
+                convergence: DEFAULT_AVBR_CONVERGENCE,
+            }),
+            "icq" => Ok(RateControl::Icq {
+                quality: DEFAULT_QUALITY,
+            }),
+            "la" => Ok(RateControl::La { depth: 0 }),
+            "la_icq" | "la-icq" => Ok(RateControl::LaIcq {
+                quality: DEFAULT_QUALITY,
+                depth: 0,
+            }),
+            "qvbr" => Ok(RateControl::Qvbr {
+                quality: DEFAULT_QUALITY,
+            }),
+            _ => Err(Error::Config(format!("unknown rate control mode '{}'", s))),
+        }
+    }
+}
+
+impl KeyframeInterval {
+    // Interval in frames at `frame_rate`.
+    fn frames(self, frame_rate: FrameRate) -> u64 {
+        match self {
+            KeyframeInterval::Frames(frames) => frames as u64,
+            KeyframeInterval::Time(time) => {
+                let num = time.as_nanos() * frame_rate.num as u128;
+                let den = NANOS_PER_SECOND * frame_rate.den as u128;
+                ((num + den / 2) / den) as u64
+            }
+        }
+    }
+}
+
+/// Parses a number of frames, or of seconds followed by `s`, such as `2s` or
+/// `0.5s`.
+impl FromStr for KeyframeInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<KeyframeInterval> {
+        let invalid = || Error::Config(format!("invalid keyframe interval '{}'", s));
+        match s.strip_suffix('s') {
+            Some(seconds) => {
+                let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+                Duration::try_from_secs_f64(seconds)
+                    .map(KeyframeInterval::Time)
+                    .map_err(|_| invalid())
+            }
+            None => s
+                .parse()
+                .map(KeyframeInterval::Frames)
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+impl InputFormat {
+    fn fourcc(self) -> mfxU32 {
+        match self {
+            InputFormat::I420 => MFX_FOURCC_YV12,
+            InputFormat::Nv12 => MFX_FOURCC_NV12,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<InputFormat> {
+        match s {
+            "i420" => Ok(InputFormat::I420),
+            "nv12" => Ok(InputFormat::Nv12),
+            _ => Err(Error::Config(format!("unknown input format '{}'", s))),
+        }
+    }
+}
+
+impl FrameControl {
+    fn validate(&self, config: &EncoderConfig) -> Result<()> {
+        if *self == FrameControl::default() {
+            return Ok(());
+        }
+        if config.codec == Codec::Jpeg {
+            return Err(Error::Config(
+                "JPEG images take no frame control".to_string(),
+            ));
+        }
+        if let Some(qp) = self.qp {
+            if !matches!(config.rate_control, RateControl::Cqp { .. }) {
+                return Err(Error::Config(
+                    "a frame QP needs CQP rate control".to_string(),
+                ));
+            }
+            let max_qp = match config.codec {
+                Codec::Vp9 | Codec::Av1 => MAX_QINDEX,
+                _ => MAX_QP,
+            };
+            if qp == 0 || qp > max_qp {
+                return Err(Error::Config(format!(
+                    "frame QP must be between 1 and {}",
+                    max_qp
+                )));
+            }
+        }
+        if self.skip {
+            if config.coding_option2.skip_frame.is_none() {
+                return Err(Error::Config(
+                    "skipping frames needs a skip frame mode".to_string(),
+                ));
+            }
+            if self.force.is_some() {
+                return Err(Error::Config(
+                    "a skipped frame cannot be forced to an I-frame".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&self, ctrl: &mut mfxEncodeCtrl) {
+        *ctrl = mfxEncodeCtrl::new();
+        ctrl.FrameType = match self.force {
+            None => MFX_FRAMETYPE_UNKNOWN,
+            Some(ForcedFrame::I) => MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF,
+            Some(ForcedFrame::Idr) => MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF | MFX_FRAMETYPE_IDR,
+        };
+        ctrl.QP = self.qp.unwrap_or(0);
+        ctrl.SkipFrame = self.skip as mfxU16;
+    }
+}
+
+impl EncoderConfig {
+    /// VBR H.264 at 30 fps from I420 frames, everything else left to the
+    /// runtime.
+    pub fn new(width: usize, height: usize, bitrate: u32) -> EncoderConfig {
+        EncoderConfig {
+            width,
+            height,
+            bitrate,
+            max_bitrate: 0,
+            buffer_size: 0,
+            initial_delay: 0,
+            frame_rate: FrameRate::new(30, 1),
+            codec: Codec::Avc,
+            profile: None,
+            level: None,
+            rate_control: RateControl::Vbr,
+            target_usage: MFX_TARGETUSAGE_BALANCED,
+            keyframe_interval: None,
+            b_frames: None,
+            idr_interval: None,
+            closed_gop: false,
+            strict_gop: false,
+            coding_option: CodingOption::default(),
+            coding_option2: CodingOption2::default(),
+            coding_option3: CodingOption3::default(),
+            input_format: InputFormat::I420,
+            async_depth: 0,
+            tile_columns: 0,
+            tile_rows: 0,
+            vp9_segmentation: None,
+            jpeg_quality: 85,
+        }
+    }
+
+    /// Checks the values the runtime would reject or silently change.
+    pub fn validate(&self) -> Result<()> {
+        // the GOP and level checks divide by the frame rate
+        if self.frame_rate.num == 0 || self.frame_rate.den == 0 {
+            return Err(Error::Config(
+                "frame rate numerator and denominator must be non-zero".to_string(),
+            ));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::Config(
+                "width and height must be non-zero".to_string(),
+            ));
+        }
+        if !self.width.is_multiple_of(2) || !self.height.is_multiple_of(2) {
+            return Err(Error::Config(
+                "width and height must be even for 4:2:0 input".to_string(),
+            ));
+        }
+        if self.width > MAX_SIZE || self.height > MAX_SIZE {
+            return Err(Error::Config(format!(
+                "width and height must be at most {}",
+                MAX_SIZE
+            )));
+        }
+        if self.codec == Codec::Jpeg {
+            if self.jpeg_quality == 0 || self.jpeg_quality > 100 {
+                return Err(Error::Config(
+                    "JPEG quality must be between 1 and 100".to_string(),
+                ));
+            }
+        } else {
+            self.rate_control.validate(self.codec)?;
+            self.validate_bitrates()?;
+            self.validate_gop()?;
+        }
+        self.validate_coding_options()?;
+        if let Some(profile) = self.profile {
+            if profile.value(self.codec).is_none() {
+                return Err(Error::Config(format!(
+                    "{:?} has no {:?} profile",
+                    self.codec, profile
+                )));
+            }
+        }
+        self.avc_level()?;
+        self.validate_tiles()?;
+        if let Some(ref segmentation) = self.vp9_segmentation {
+            self.validate_segmentation(segmentation)?;
+        }
+        if self.target_usage < MFX_TARGETUSAGE_1 || self.target_usage > MFX_TARGETUSAGE_7 {
+            return Err(Error::Config(
+                "target usage must be between 1 and 7".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_bitrates(&self) -> Result<()> {
+        let rate_control = self.rate_control;
+        if rate_control.uses_bitrate() && self.bitrate == 0 {
+            return Err(Error::Config("bitrate must be non-zero".to_string()));
+        }
+        if self.max_bitrate != 0 {
+            if !rate_control.uses_max_bitrate() {
+                return Err(Error::Config(
+                    "a max bitrate only applies to VBR and QVBR".to_string(),
+                ));
+            }
+            if self.max_bitrate < self.bitrate {
+                return Err(Error::Config(
+                    "max bitrate must be at least the bitrate".to_string(),
+                ));
+            }
+        }
+        if self.initial_delay != 0 {
+            if !rate_control.uses_initial_delay() {
+                return Err(Error::Config(
+                    "an initial delay only applies to CBR, VBR, LA and QVBR".to_string(),
+                ));
+            }
+            if self.buffer_size != 0 && self.initial_delay > self.buffer_size {
+                return Err(Error::Config(
+                    "initial delay must be at most the buffer size".to_string(),
+                ));
+            }
+        }
+        let largest = self
+            .bitrate
+            .max(self.max_bitrate)
+            .max(self.buffer_size)
+            .max(self.initial_delay);
+        if largest > MAX_BRC_VALUE {
+            return Err(Error::Config(format!(
+                "bitrates and buffer sizes must be at most {}",
+                MAX_BRC_VALUE
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_gop(&self) -> Result<()> {
+        let gop_size = self.keyframe_interval.map(|i| i.frames(self.frame_rate));
+        if gop_size == Some(0) || gop_size > Some(u16::MAX as u64) {
+            return Err(Error::Config(format!(
+                "the keyframe interval must be 1 to {} frames",
+                u16::MAX
+            )));
+        }
+        let b_or_idr = self.b_frames.is_some() || self.idr_interval.is_some();
+        if b_or_idr && !matches!(self.codec, Codec::Avc | Codec::Hevc) {
+            return Err(Error::Config(format!(
+                "{:?} has no B-frames or IDR interval",
+                self.codec
+            )));
+        }
+        if let Some(b_frames) = self.b_frames {
+            let baseline = matches!(
+                self.profile,
+                Some(Profile::Baseline) | Some(Profile::ConstrainedBaseline)
+            );
+            if baseline && b_frames != 0 {
+                return Err(Error::Config(
+                    "the Baseline profiles have no B-frames".to_string(),
+                ));
+            }
+            if b_frames > MAX_B_FRAMES {
+                return Err(Error::Config(format!(
+                    "at most {} B-frames are supported",
+                    MAX_B_FRAMES
+                )));
+            }
+            if gop_size.is_some_and(|size| b_frames as u64 >= size) {
+                return Err(Error::Config(
+                    "B-frames must be fewer than the keyframe interval".to_string(),
+                ));
+            }
+        }
+        if self.idr_interval == Some(0) {
+            return Err(Error::Config(
+                "the IDR interval must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Coding options have to suit the codec, profile and GOP.
+    fn validate_coding_options(&self) -> Result<()> {
+        let (co, co2, co3) = (
+            &self.coding_option,
+            &self.coding_option2,
+            &self.coding_option3,
+        );
+        if *co == CodingOption::default()
+            && *co2 == CodingOption2::default()
+            && *co3 == CodingOption3::default()
+        {
+            return Ok(());
+        }
+        if !matches!(self.codec, Codec::Avc | Codec::Hevc) {
+            return Err(Error::Config(
+                "coding options are only supported for H.264 and HEVC".to_string(),
+            ));
+        }
+        let baseline = matches!(
+            self.profile,
+            Some(Profile::Baseline) | Some(Profile::ConstrainedBaseline)
+        );
+        match co.cavlc {
+            Some(true) if self.codec != Codec::Avc => {
+                return Err(Error::Config("CAVLC is H.264 only".to_string()));
+            }
+            Some(false) if baseline => {
+                return Err(Error::Config(
+                    "the Baseline profiles have no CABAC".to_string(),
+                ));
+            }
+            _ => {}
+        }
+        if co3.gpb.is_some() && self.codec != Codec::Hevc {
+            return Err(Error::Config("GPB is HEVC only".to_string()));
+        }
+        if co3.weighted_pred == Some(WeightedPrediction::Implicit) {
+            return Err(Error::Config(
+                "implicit weighted prediction is for B-frames only".to_string(),
+            ));
+        }
+        if co2.adaptive_i == Some(true) && self.strict_gop {
+            return Err(Error::Config(
+                "adaptive I-frames need a GOP that is not strict".to_string(),
+            ));
+        }
+        let no_b_frames = self.b_frames == Some(0) || baseline;
+        if no_b_frames && (co2.adaptive_b == Some(true) || co2.b_pyramid == Some(true)) {
+            return Err(Error::Config(
+                "adaptive B-frames and B-pyramids need B-frames".to_string(),
+            ));
+        }
+        if matches!(co.max_dec_frame_buffering, Some(n) if n > 16) {
+            return Err(Error::Config(
+                "max decoded frame buffering must be at most 16".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Level of an H.264 stream, the configured one once checked against
+    // Table A-1, or the lowest one the stream fits in.
+    fn avc_level(&self) -> Result<Option<Level>> {
+        if self.codec != Codec::Avc {
+            return match self.level {
+                Some(_) => Err(Error::Config(
+                    "levels are only supported for H.264".to_string(),
+                )),
+                None => Ok(None),
+            };
+        }
+        let bitrate = if self.rate_control.uses_bitrate() {
+            self.bitrate.max(self.max_bitrate)
+        } else {
+            0
+        };
+        let requirements = LevelRequirements {
+            width: self.width,
+            height: self.height,
+            frame_rate: self.frame_rate,
+            bitrate,
+            buffer_size: self.buffer_size,
+            profile: self.profile,
+        };
+        match self.level {
+            Some(level) => level.check(&requirements).map(|()| Some(level)),
+            None => Level::lowest(&requirements).map(Some),
+        }
+    }
+
+    fn validate_tiles(&self) -> Result<()> {
+        if self.tile_columns == 0 && self.tile_rows == 0 {
+            return Ok(());
+        }
+        match self.codec {
+            Codec::Av1 => {
+                if self.tile_columns > AV1_MAX_TILES || self.tile_rows > AV1_MAX_TILES {
+                    return Err(Error::Config(format!(
+                        "AV1 has at most {} tile columns and rows",
+                        AV1_MAX_TILES
+                    )));
+                }
+            }
+            Codec::Vp9 => {
+                let columns = self.tile_columns.max(1);
+                if !columns.is_power_of_two() || columns > VP9_MAX_TILE_COLUMNS {
+                    return Err(Error::Config(format!(
+                        "VP9 tile columns must be a power of two up to {}",
+                        VP9_MAX_TILE_COLUMNS
+                    )));
+                }
+                let rows = self.tile_rows.max(1);
+                if !rows.is_power_of_two() || rows > VP9_MAX_TILE_ROWS {
+                    return Err(Error::Config(format!(
+                        "VP9 tile rows must be 1, 2 or {}",
+                        VP9_MAX_TILE_ROWS
+                    )));
+                }
+            }
+            _ => return Err(Error::Config(format!("{:?} has no tiles", self.codec))),
+        }
+        Ok(())
+    }
+
+    fn validate_segmentation(&self, segmentation: &Vp9Segmentation) -> Result<()> {
+        if self.codec != Codec::Vp9 {
+            return Err(Error::Config(
+                "segmentation is only supported for VP9".to_string(),
+            ));
+        }
+        let segments = segmentation.segments.len();
+        if segments == 0 || segments > VP9_MAX_SEGMENTS {
+            return Err(Error::Config(format!(
+                "VP9 has 1 to {} segments",
+                VP9_MAX_SEGMENTS
+            )));
+        }
+        for segment in &segmentation.segments {
+            if segment.qindex_delta.abs() > 255 || segment.loop_filter_level_delta.abs() > 63 {
+                return Err(Error::Config(
+                    "VP9 segment deltas must be within -255..255 for the quantizer \
+                     and -63..63 for the loop filter"
+                        .to_string(),
+                ));
+            }
+        }
+        let size = segmentation.block_size as usize;
+        if ![8, 16, 32, 64].contains(&size) {
+            return Err(Error::Config(
+                "VP9 segment blocks must be 8, 16, 32 or 64 pixels wide".to_string(),
+            ));
+        }
+        // the runtime reads the map over the 16-aligned frame
+        let width = self.width.next_multiple_of(16);
+        let height = self.height.next_multiple_of(16);
+        let blocks = width.div_ceil(size) * height.div_ceil(size);
+        if segmentation.segment_ids.len() != blocks {
+            return Err(Error::Config(format!(
+                "the segment map must have {} entries, one per {}x{} block",
+                blocks, size, size
+            )));
+        }
+        if segmentation
+            .segment_ids
+            .iter()
+            .any(|&id| id as usize >= segments)
+        {
+            return Err(Error::Config(
+                "the segment map refers to a missing segment".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Bitrates and buffer sizes of `mfx`, multiplied back by BRCParamMultiplier.
+fn bitrate_params(mfx: &mfxInfoMFX) -> BitrateParams {
+    // 0 has the same meaning as 1
+    let multiplier = mfx.BRCParamMultiplier.max(1) as u32;
+    let scaled = |value: mfxU16, used: bool| if used { value as u32 * multiplier } else { 0 };
+    let method = mfx.RateControlMethod;
+    let target = !matches!(
+        method,
+        MFX_RATECONTROL_CQP | MFX_RATECONTROL_ICQ | MFX_RATECONTROL_LA_ICQ
+    );
+    let max = matches!(
+        method,
+        MFX_RATECONTROL_VBR | MFX_RATECONTROL_QVBR | MFX_RATECONTROL_LA_HRD | MFX_RATECONTROL_VCM
+    );
+    let delay = matches!(
+        method,
+        MFX_RATECONTROL_CBR
+            | MFX_RATECONTROL_VBR
+            | MFX_RATECONTROL_LA
+            | MFX_RATECONTROL_QVBR
+            | MFX_RATECONTROL_LA_HRD
+            | MFX_RATECONTROL_VCM
+    );
+    unsafe {
+        BitrateParams {
+            target_kbps: scaled(mfx.u2.TargetKbps, target),
+            max_kbps: scaled(mfx.u3.MaxKbps, max),
+            buffer_size_kb: scaled(mfx.BufferSizeInKB, true),
+            initial_delay_kb: scaled(mfx.u1.InitialDelayInKB, delay),
+        }
+    }
+}
+
+fn vpp_params(config: &EncoderConfig) -> mfxVideoParam {
+    let width = config.width as u16;
+    let height = config.height as u16;
+
+    let mut VppParams = mfxVideoParam::new();
+    VppParams.u.vpp.In.FourCC = config.input_format.fourcc();
+    VppParams.u.vpp.In.ChromaFormat = MFX_CHROMAFORMAT_YUV420;
+    VppParams.u.vpp.In.CropX = 0;
+    VppParams.u.vpp.In.CropY = 0;
+    VppParams.u.vpp.In.CropW = width;
+    VppParams.u.vpp.In.CropH = height;
+    VppParams.u.vpp.In.PicStruct = MFX_PICSTRUCT_PROGRESSIVE;
+    VppParams.u.vpp.In.FrameRateExtN = config.frame_rate.num;
+    VppParams.u.vpp.In.FrameRateExtD = config.frame_rate.den;
+    VppParams.u.vpp.In.Width = align16(width);
+    VppParams.u.vpp.In.Height = align16(height);
+
+    // VPP converts to whatever the encoder profile needs
+    let (fourcc, chromaFormat, bitDepth, shift) = Profile::surface_format(config.profile);
+    VppParams.u.vpp.Out.FourCC = fourcc;
+    VppParams.u.vpp.Out.ChromaFormat = chromaFormat;
+    VppParams.u.vpp.Out.BitDepthLuma = bitDepth;
+    VppParams.u.vpp.Out.BitDepthChroma = bitDepth;
+    VppParams.u.vpp.Out.Shift = shift;
+    VppParams.u.vpp.Out.CropX = 0;
+    VppParams.u.vpp.Out.CropY = 0;
+    VppParams.u.vpp.Out.CropW = width;
+    VppParams.u.vpp.Out.CropH = height;
+    VppParams.u.vpp.Out.PicStruct = MFX_PICSTRUCT_PROGRESSIVE;
+    VppParams.u.vpp.Out.FrameRateExtN = config.frame_rate.num;
+    VppParams.u.vpp.Out.FrameRateExtD = config.frame_rate.den;
+    VppParams.u.vpp.Out.Width = align16(width);
+    VppParams.u.vpp.Out.Height = align16(height);
+    VppParams.IOPattern = MFX_IOPATTERN_IN_SYSTEM_MEMORY | MFX_IOPATTERN_OUT_SYSTEM_MEMORY;
+    VppParams.AsyncDepth = config.async_depth;
+    VppParams
+}
+
+// Builds the ENCODE parameters for `config` and runs `f` on them. The
+// extension buffers they point to only live for the duration of the call.
+fn with_encode_params<T, F>(config: &EncoderConfig, f: F) -> Result<T>
+where
+    F: FnOnce(&mut mfxVideoParam) -> Result<T>,
+{
+    let width = config.width as u16;
+    let height = config.height as u16;
+    let (fourcc, chromaFormat, bitDepth, shift) = Profile::surface_format(config.profile);
+
+    let mut EncParams = mfxVideoParam::new();
+    EncParams.u.mfx.CodecId = config.codec.id();
+    EncParams.u.mfx.CodecProfile = config
+        .profile
+        .and_then(|p| p.value(config.codec))
+        .unwrap_or(MFX_PROFILE_UNKNOWN);
+    EncParams.u.mfx.CodecLevel = config.avc_level()?.map_or(MFX_LEVEL_UNKNOWN, Level::value);
+    if config.codec == Codec::Jpeg {
+        EncParams.u.jpeg.Interleaved = MFX_SCANTYPE_INTERLEAVED;
+        EncParams.u.jpeg.Quality = config.jpeg_quality;
+    } else {
+        EncParams.u.mfx.TargetUsage = config.target_usage;
+        config
+            .rate_control
+            .set(unsafe { &mut EncParams.u.mfx }, config);
+        if let Some(interval) = config.keyframe_interval {
+            EncParams.u.mfx.GopPicSize = interval.frames(config.frame_rate) as u16;
+        }
+        if let Some(b_frames) = config.b_frames {
+            EncParams.u.mfx.GopRefDist = b_frames + 1;
+        }
+        let mut gopOptFlag = 0;
+        if config.closed_gop {
+            gopOptFlag |= MFX_GOP_CLOSED;
+        }
+        if config.strict_gop {
+            gopOptFlag |= MFX_GOP_STRICT;
+        }
+        EncParams.u.mfx.GopOptFlag = gopOptFlag;
+        // H.264 counts the I-frames between IDR frames, HEVC every nth
+        // I-frame is an IDR frame
+        EncParams.u.mfx.IdrInterval = match (config.codec, config.idr_interval) {
+            (Codec::Avc, Some(n)) => n - 1,
+            (_, Some(n)) => n,
+            (_, None) => 0,
+        };
+    }
+    EncParams.u.mfx.FrameInfo.FrameRateExtN = config.frame_rate.num;
+    EncParams.u.mfx.FrameInfo.FrameRateExtD = config.frame_rate.den;
+    EncParams.u.mfx.FrameInfo.FourCC = fourcc;
+    EncParams.u.mfx.FrameInfo.ChromaFormat = chromaFormat;
+    EncParams.u.mfx.FrameInfo.BitDepthLuma = bitDepth;
+    EncParams.u.mfx.FrameInfo.BitDepthChroma = bitDepth;
+    EncParams.u.mfx.FrameInfo.Shift = shift;
+    EncParams.u.mfx.FrameInfo.PicStruct = MFX_PICSTRUCT_PROGRESSIVE;
+    EncParams.u.mfx.FrameInfo.CropX = 0;
+    EncParams.u.mfx.FrameInfo.CropY = 0;
+    EncParams.u.mfx.FrameInfo.CropW = width;
+    EncParams.u.mfx.FrameInfo.CropH = height;
+    EncParams.u.mfx.FrameInfo.Width = align16(width);
+    EncParams.u.mfx.FrameInfo.Height = align16(height);
+    EncParams.IOPattern = MFX_IOPATTERN_IN_SYSTEM_MEMORY;
+    EncParams.AsyncDepth = config.async_depth;
+
+    // codec settings living outside mfxInfoMFX, referenced until init
+    let mut av1TileParam = mfxExtAV1TileParam::new();
+    av1TileParam.NumTileColumns = config.tile_columns;
+    av1TileParam.NumTileRows = config.tile_rows;
+    let mut vp9Param = mfxExtVP9Param::new();
+    vp9Param.NumTileColumns = config.tile_columns;
+    vp9Param.NumTileRows = config.tile_rows;
+    // packets are bare frames, the caller picks the container
+    vp9Param.WriteIVFHeaders = MFX_CODINGOPTION_OFF;
+    let mut vp9Segmentation = mfxExtVP9Segmentation::new();
+    let mut codingOption = mfxExtCodingOption::new();
+    config.coding_option.write(&mut codingOption);
+    let mut codingOption2 = mfxExtCodingOption2::new();
+    config.coding_option2.write(&mut codingOption2);
+    let mut codingOption3 = mfxExtCodingOption3::new();
+    config.coding_option3.write(&mut codingOption3);
+    let mut extParams: Vec<*const mfxExtBuffer> = Vec::new();
+    // the rate control settings share the buffers with the coding options
+    let mut useCodingOption2 = config.coding_option2 != CodingOption2::default();
+    let mut useCodingOption3 = config.coding_option3 != CodingOption3::default();
+    match config.rate_control {
+        // JPEG has no rate control
+        _ if config.codec == Codec::Jpeg => {}
+        RateControl::La { depth } | RateControl::LaIcq { depth, .. } if depth != 0 => {
+            codingOption2.LookAheadDepth = depth;
+            useCodingOption2 = true;
+        }
+        RateControl::Qvbr { quality } => {
+            codingOption3.QVBRQuality = quality;
+            useCodingOption3 = true;
+        }
+        _ => {}
+    }
+    if config.coding_option != CodingOption::default() {
+        extParams.push(&codingOption.Header);
+    }
+    if useCodingOption2 {
+        extParams.push(&codingOption2.Header);
+    }
+    if useCodingOption3 {
+        extParams.push(&codingOption3.Header);
+    }
+    match config.codec {
+        Codec::Av1 if config.tile_columns != 0 || config.tile_rows != 0 => {
+            extParams.push(&av1TileParam.Header);
+        }
+        Codec::Vp9 => {
+            extParams.push(&vp9Param.Header);
+            if let Some(ref segmentation) = config.vp9_segmentation {
+                vp9Segmentation.NumSegments = segmentation.segments.len() as u16;
+                for (dst, src) in vp9Segmentation
+                    .Segment
+                    .iter_mut()
+                    .zip(&segmentation.segments)
+                {
+                    if src.qindex_delta != 0 {
+                        dst.FeatureEnabled |= MFX_VP9_SEGMENT_FEATURE_QINDEX;
+                        dst.QIndexDelta = src.qindex_delta;
+                    }
+                    if src.loop_filter_level_delta != 0 {
+                        dst.FeatureEnabled |= MFX_VP9_SEGMENT_FEATURE_LOOP_FILTER;
+                        dst.LoopFilterLevelDelta = src.loop_filter_level_delta;
+                    }
+                }
+                vp9Segmentation.SegmentIdBlockSize = segmentation.block_size;
+                vp9Segmentation.NumSegmentIdAlloc = segmentation.segment_ids.len() as u32;
+                // only read by the runtime
+                vp9Segmentation.SegmentId = segmentation.segment_ids.as_ptr() as *mut u8;
+                extParams.push(&vp9Segmentation.Header);
+            }
+        }
+        _ => {}
+    }
+    EncParams.ExtParam = extParams.as_ptr();
+    EncParams.NumExtParam = extParams.len() as u16;
+
+    f(&mut EncParams)
+}
+
+// Queries `EncParams`, falling back to the LowPower engine where needed.
+fn query_encode(
+    session: &Session,
+    config: &EncoderConfig,
+    EncParams: &mut mfxVideoParam,
+) -> Result<()> {
+    // MFX_WRN_INCOMPATIBLE_VIDEO_PARAM: the runtime corrected the parameters in place.
+    // Query a copy, since rejected fields are zeroed.
+    let mut queried = *EncParams;
+    let result = session.encode_query(&mut queried);
+    let result = match result {
+        // some GPUs only encode HEVC, VP9 and AV1 on the low-power (VDENC) engine
+        Err(ref e)
+            if e.mfx_error() == Some(MfxError::Unsupported)
+                && matches!(config.codec, Codec::Hevc | Codec::Vp9 | Codec::Av1) =>
+        {
+            EncParams.u.mfx.LowPower = MFX_CODINGOPTION_ON;
+            queried = *EncParams;
+            session.encode_query(&mut queried)
+        }
+        result => result,
+    };
+    result?;
+    *EncParams = queried;
+    Ok(())
+}
+
+// Size of the bitstream buffer holding one encoded frame.
+fn bitstream_size(session: &Session, config: &EncoderConfig) -> Result<usize> {
+    // the JPEG options leave no room for BufferSizeInKB
+    if config.codec == Codec::Jpeg {
+        return Ok(config.width * config.height * JPEG_BYTES_PER_PIXEL);
+    }
+    let par = session.encode_get_video_param()?;
+    Ok(1000 * bitrate_params(unsafe { &par.u.mfx }).buffer_size_kb as usize)
+}
+
+// Request for the pool VPP writes to and ENCODE reads from, which holds the
+// surfaces of both.
+fn shared_request(vpp: &mfxFrameAllocRequest, enc: &mfxFrameAllocRequest) -> mfxFrameAllocRequest {
+    let mut request = mfxFrameAllocRequest::new();
+    request.Info = enc.Info;
+    request.Info.Width = vpp.Info.Width.max(enc.Info.Width);
+    request.Info.Height = vpp.Info.Height.max(enc.Info.Height);
+    request.NumFrameMin = vpp.NumFrameMin + enc.NumFrameMin;
+    request.NumFrameSuggested = vpp.NumFrameSuggested + enc.NumFrameSuggested;
+    request
+}
+
+// A reconfigured pipeline has to fit in the surfaces allocated for `initial`.
+fn check_alloc_request(
+    request: &mfxFrameAllocRequest,
+    initial: &mfxFrameAllocRequest,
+    what: &str,
+) -> Result<()> {
+    let (info, allocated) = (&request.Info, &initial.Info);
+    if info.Width > allocated.Width || info.Height > allocated.Height {
+        return Err(Error::Config(format!(
+            "{}x{} frames for the {} exceed the {}x{} surfaces allocated at init",
+            info.CropW, info.CropH, what, allocated.Width, allocated.Height
+        )));
+    }
+    if request.NumFrameMin > initial.NumFrameSuggested {
+        return Err(Error::Config(format!(
+            "{} surfaces are needed for the {}, {} were allocated at init",
+            request.NumFrameMin, what, initial.NumFrameSuggested
+        )));
+    }
+    Ok(())
+}
+
+fn align16(x: u16) -> u16 {
+    ((x + 15) >> 4) << 4
+}
+
+fn is_more_data<T>(result: &Result<T>) -> bool {
+    match *result {
+        Err(ref e) => e.mfx_error() == Some(MfxError::MoreData),
+        Ok(_) => false,
+    }
+}
+
+// Copies a planar Y, U, V frame into a YV12 surface, or an NV12 frame into
+// an NV12 surface, honouring the surface pitch.
+fn LoadRawFrame(surface: &mut mfxFrameSurface1, frame: &[u8]) {
+    let w = surface.Info.CropW as usize;
+    let h = surface.Info.CropH as usize;
+    let pitch = surface.Data.PitchLow as usize;
+
+    let (y, chroma) = frame.split_at(w * h);
+    let planes = if surface.Info.FourCC == MFX_FOURCC_NV12 {
+        vec![
+            (surface.Data.Y, y, w, h, pitch),
+            (surface.Data.UV, chroma, w, h / 2, pitch),
+        ]
+    } else {
+        let (u, v) = chroma.split_at(w * h / 4);
+        vec![
+            (surface.Data.Y, y, w, h, pitch),
+            (surface.Data.UV, u, w / 2, h / 2, pitch / 2),
+            (surface.Data.V, v, w / 2, h / 2, pitch / 2),
+        ]
+    };
+    for &(dst, src, w, h, pitch) in planes.iter() {
+        for row in 0..h {
+            let line = unsafe { slice::from_raw_parts_mut(dst.add(row * pitch), w) };
+            line.copy_from_slice(&src[row * w..(row + 1) * w]);
+        }
+    }
+}
+
+fn ReadBitStreamFrame(pMfxBitstream: &mut mfxBitstream) -> Packet {
+    let buffer = unsafe {
+        slice::from_raw_parts(
+            pMfxBitstream.Data.add(pMfxBitstream.DataOffset as usize),
+            pMfxBitstream.DataLength as usize,
+        )
+    };
+    let packet = Packet {
+        data: buffer.to_vec(),
+        timestamp: pMfxBitstream.TimeStamp,
+        frame_type: pMfxBitstream.FrameType,
+    };
+    pMfxBitstream.DataOffset = 0;
+    pMfxBitstream.DataLength = 0;
+    packet
+}
+
+impl Encoder {
+    /// Loads the runtime, opens a hardware session and initializes VPP and
+    /// ENCODE for `config`.
+    pub fn new(config: &EncoderConfig) -> Result<Encoder> {
+        Encoder::with_backend(config, Arc::new(SdkBackend::load()?))
+    }
+
+    /// Same as [`Encoder::new`], routing every Media SDK call through
+    /// `backend`.
+    pub fn with_backend(config: &EncoderConfig, backend: Arc<dyn Backend>) -> Result<Encoder> {
+        config.validate()?;
+
+        let implementation = MFX_IMPL_HARDWARE_ANY;
+        let version = mfxVersion::new(1, 0);
+        let mut session = Session::new(backend, implementation, &version, config.codec.id())?;
+
+        let mut VppParams = vpp_params(config);
+
+        let VPPRequest = session.vpp_query_io_surf(&VppParams)?;
+
+        let vpp_pool_in = SurfacePool::new(unsafe { &VppParams.u.vpp.In }, &VPPRequest[0]);
+
+        session.init_vpp(&mut VppParams)?;
+
+        session.load_encoder_plugin(config.codec.id());
+
+        let encRequest = with_encode_params(config, |EncParams| {
+            query_encode(&session, config, EncParams)?;
+
+            let encRequest = session.encode_query_io_surf(EncParams)?;
+
+            session.init_encode(EncParams)?;
+            Ok(encRequest)
+        })?;
+        let sharedRequest = shared_request(&VPPRequest[1], &encRequest);
+        let shared_pool = SurfacePool::new(&sharedRequest.Info, &sharedRequest);
+
+        let bitstreamSize = bitstream_size(&session, config)?;
+        // the runtime resolves a default async depth of 0
+        let pipelineDepth = session.encode_get_video_param()?.AsyncDepth.max(1) as usize;
+
+        Ok(Encoder {
+            session,
+            config: config.clone(),
+            vpp_pool_in,
+            enc_ctrls: vec![mfxEncodeCtrl::new(); shared_pool.capacity()],
+            shared_pool,
+            vpp_requests: VPPRequest,
+            enc_request: encRequest,
+            pending: VecDeque::with_capacity(pipelineDepth),
+            bitstreams: Vec::with_capacity(pipelineDepth),
+            bitstream_size: bitstreamSize,
+            pipeline_depth: pipelineDepth,
+            timestamp_base: 0,
+            frame_count: 0,
+            state: StreamState::Drained,
+        })
+    }
+
+    /// Switches to `config` mid-stream through `MFXVideoENCODE_Reset`, and
+    /// `MFXVideoVPP_Reset` when the frame size or rate changes, keeping the
+    /// session and surfaces. The codec, input format, surface format and
+    /// async depth cannot change, and the frame size cannot outgrow the
+    /// surfaces allocated at init. The runtime drops the frames it holds on
+    /// reset, so the encoder has to be drained with [`Encoder::flush`]
+    /// first. `new_sequence` starts a new sequence with an IDR frame, as a
+    /// new frame size always does. An encoder that fails to reset cannot be
+    /// used any further.
+    pub fn reconfigure(
+        &mut self,
+        config: &EncoderConfig,
+        new_sequence: bool,
+    ) -> Result<Reconfiguration> {
+        self.check_usable()?;
+        config.validate()?;
+        let old = &self.config;
+        let fixed = [
+            ("codec", config.codec != old.codec),
+            ("input format", config.input_format != old.input_format),
+            (
+                "surface format",
+                Profile::surface_format(config.profile) != Profile::surface_format(old.profile),
+            ),
+            ("async depth", config.async_depth != old.async_depth),
+        ];
+        if let Some((what, _)) = fixed.iter().find(|(_, changed)| *changed) {
+            return Err(Error::Config(format!(
+                "the {} cannot change mid-stream",
+                what
+            )));
+        }
+        let vppChanged = config.width != old.width
+            || config.height != old.height
+            || config.frame_rate != old.frame_rate;
+
+        let mut VppParams = vpp_params(config);
+        let VPPRequest = self.session.vpp_query_io_surf(&VppParams)?;
+        check_alloc_request(&VPPRequest[0], &self.vpp_requests[0], "VPP input")?;
+        // stay on the engine picked at init
+        let lowPower = unsafe { self.session.encode_get_video_param()?.u.mfx.LowPower };
+        let encRequest = with_encode_params(config, |EncParams| {
+            EncParams.u.mfx.LowPower = lowPower;
+            self.session.encode_query_io_surf(EncParams)
+        })?;
+        // VPP output and ENCODE draw from the one pool
+        check_alloc_request(
+            &shared_request(&VPPRequest[1], &encRequest),
+            &shared_request(&self.vpp_requests[1], &self.enc_request),
+            "VPP output and encoder",
+        )?;
+
+        if self.state != StreamState::Drained {
+            return Err(Error::Config(
+                "frames are still buffered, the encoder has to be flushed first".to_string(),
+            ));
+        }
+        // until VPP, ENCODE and the pools all follow the new configuration
+        self.state = StreamState::Broken;
+
+        if vppChanged {
+            self.session.vpp_reset(&mut VppParams)?;
+        }
+        let session = &mut self.session;
+        let (in_place, encInfo) = with_encode_params(config, |EncParams| {
+            EncParams.u.mfx.LowPower = lowPower;
+            let mut resetOption = mfxExtEncoderResetOption::new();
+            resetOption.StartNewSequence = if new_sequence {
+                MFX_CODINGOPTION_ON
+            } else {
+                MFX_CODINGOPTION_OFF
+            };
+            let mut extParams = unsafe {
+                slice::from_raw_parts(EncParams.ExtParam, EncParams.NumExtParam as usize)
+            }
+            .to_vec();
+            extParams.push(&resetOption.Header);
+            let mut resetParams = *EncParams;
+            resetParams.ExtParam = extParams.as_ptr();
+            resetParams.NumExtParam = extParams.len() as u16;
+
+            let in_place = match session.encode_reset(&resetParams) {
+                Ok(_) => true,
+                // the change needs a new encoder
+                Err(ref e) if e.mfx_error() == Some(MfxError::IncompatibleVideoParam) => {
+                    session.close_encode();
+                    session.init_encode(EncParams)?;
+                    false
+                }
+                Err(e) => return Err(e),
+            };
+            Ok((in_place, unsafe { EncParams.u.mfx.FrameInfo }))
+        })?;
+
+        self.vpp_pool_in.set_frame(unsafe { &VppParams.u.vpp.In });
+        self.shared_pool.set_frame(&encInfo);
+
+        // nothing is in flight after the flush
+        let bitstreamSize = bitstream_size(&self.session, config)?;
+        if bitstreamSize > self.bitstream_size {
+            self.bitstream_size = bitstreamSize;
+            self.bitstreams.clear();
+        }
+
+        self.timestamp_base += self.config.frame_rate.timestamp(self.frame_count);
+        self.frame_count = 0;
+        self.config = config.clone();
+        self.state = StreamState::Drained;
+
+        Ok(Reconfiguration { in_place })
+    }
+
+    /// Configuration the encoder was created with, or last reconfigured to.
+    pub fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    /// Media SDK implementation (`MFX_IMPL_*`) the session runs on.
+    pub fn implementation(&self) -> Result<i32> {
+        self.session.query_impl()
+    }
+
+    /// Bitrates and buffer sizes the runtime settled on, which may differ
+    /// from the configured ones. JPEG has none.
+    pub fn bitrate_params(&self) -> Result<BitrateParams> {
+        if self.config.codec == Codec::Jpeg {
+            return Err(Error::Config("JPEG has no rate control".to_string()));
+        }
+        let par = self.session.encode_get_video_param()?;
+        Ok(bitrate_params(unsafe { &par.u.mfx }))
+    }
+
+    /// Coding options the runtime settled on, with the ones left to it
+    /// resolved. H.264 and HEVC only.
+    pub fn coding_options(&self) -> Result<(CodingOption, CodingOption2, CodingOption3)> {
+        if !matches!(self.config.codec, Codec::Avc | Codec::Hevc) {
+            return Err(Error::Config(
+                "coding options are only supported for H.264 and HEVC".to_string(),
+            ));
+        }
+        let mut codingOption = mfxExtCodingOption::new();
+        let mut codingOption2 = mfxExtCodingOption2::new();
+        let mut codingOption3 = mfxExtCodingOption3::new();
+        self.session.encode_get_coding_options(
+            &mut codingOption,
+            &mut codingOption2,
+            &mut codingOption3,
+        )?;
+        Ok((
+            CodingOption::read(&codingOption),
+            CodingOption2::read(&codingOption2),
+            CodingOption3::read(&codingOption3),
+        ))
+    }
+
+    /// Size in bytes of one YUV 4:2:0 input frame.
+    pub fn frame_size(&self) -> usize {
+        self.config.width * self.config.height * 3 / 2
+    }
+
+    /// Submits one frame in the configured input format and returns the packets that became
+    /// available, which may be none while the encoder is buffering. Up to
+    /// [`EncoderConfig::async_depth`] frames are kept in flight, waiting for
+    /// the oldest only once that many are.
+    pub fn encode(&mut self, frame: &[u8]) -> Result<Vec<Packet>> {
+        self.encode_with_control(frame, &FrameControl::default())
+    }
+
+    /// Same as [`Encoder::encode`], with `control` applying to this frame.
+    pub fn encode_with_control(
+        &mut self,
+        frame: &[u8],
+        control: &FrameControl,
+    ) -> Result<Vec<Packet>> {
+        self.check_usable()?;
+        control.validate(&self.config)?;
+        if frame.len() != self.frame_size() {
+            return Err(Error::InvalidFrame {
+                expected: self.frame_size(),
+                actual: frame.len(),
+            });
+        }
+        self.state = StreamState::Encoding;
+
+        let mut packets = Vec::new();
+        let mut surfaceIn = self.lease_surface(|e| &mut e.vpp_pool_in, &mut packets)?;
+        LoadRawFrame(surfaceIn.surface_mut(), frame);
+        surfaceIn.surface_mut().Data.TimeStamp =
+            self.timestamp_base + self.config.frame_rate.timestamp(self.frame_count);
+        self.frame_count += 1;
+
+        if let Some(surfaceOut) = self.run_vpp(Some(&surfaceIn), &mut packets)? {
+            self.encode_vpp_output(&surfaceOut, control, &mut packets)?;
+        }
+
+        Ok(packets)
+    }
+
+    /// Encodes one frame into a standalone JPEG image. JPEG frames are
+    /// independent, so the image is returned right away and the encoder
+    /// stays usable for the next one. Images still in flight from
+    /// [`Encoder::encode`] have to be flushed first.
+    pub fn encode_image(&mut self, frame: &[u8]) -> Result<Packet> {
+        if self.config.codec != Codec::Jpeg {
+            return Err(Error::Config(format!(
+                "{:?} frames are not standalone images",
+                self.config.codec
+            )));
+        }
+        // their packets would come back along with this image
+        if !self.pending.is_empty() {
+            return Err(Error::Config(
+                "frames submitted with encode are still in flight".to_string(),
+            ));
+        }
+        let mut packets = self.encode(frame)?;
+        self.sync_pending(0, &mut packets)?;
+        packets.pop().ok_or(Error::Mfx {
+            function: "MFXVideoENCODE_EncodeFrameAsync",
+            error: MfxError::MoreData,
+        })
+    }
+
+    /// Signals end of stream and drains the frames still buffered in VPP and
+    /// ENCODE, returning their packets. No further frames may be submitted
+    /// but after [`Encoder::reconfigure`].
+    pub fn flush(&mut self) -> Result<Vec<Packet>> {
+        self.check_usable()?;
+        let mut packets = Vec::new();
+
+        // Stage 2: Retrieve the frames buffered in VPP
+        while let Some(surfaceOut) = self.run_vpp(None, &mut packets)? {
+            let control = FrameControl::default();
+            self.encode_vpp_output(&surfaceOut, &control, &mut packets)?;
+        }
+
+        // Stage 3: Retrieve the frames buffered in ENCODE
+        while self.submit_surface(None)? {
+            self.sync_pending(self.pipeline_depth - 1, &mut packets)?;
+        }
+
+        // Stage 4: Wait for the frames still in flight
+        self.sync_pending(0, &mut packets)?;
+        self.state = StreamState::Drained;
+
+        Ok(packets)
+    }
+
+    fn check_usable(&self) -> Result<()> {
+        if self.state == StreamState::Broken {
+            return Err(Error::Config(
+                "the encoder is unusable after a failed reconfiguration".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Runs VPP on the given input surface, or drains it when there is none.
+    // Returns the output surface, or None when VPP needs more data. The output
+    // is not synchronized, ENCODE waits for it.
+    fn run_vpp(
+        &mut self,
+        surfaceIn: Option<&SurfaceLease>,
+        packets: &mut Vec<Packet>,
+    ) -> Result<Option<SurfaceLease>> {
+        let mut surfaceOut = self.lease_surface(|e| &mut e.shared_pool, packets)?;
+
+        let mut syncp_vpp: mfxSyncPoint = ptr::null();
+        let result = loop {
+            let result = self.session.vpp_run_frame_async(
+                surfaceIn.map(SurfaceLease::surface),
+                surfaceOut.surface_mut(),
+                &mut syncp_vpp,
+            );
+            match result {
+                Ok(Some(MfxWarning::DeviceBusy)) => thread::sleep(DEVICE_BUSY_WAIT),
+                _ => break result,
+            }
+        };
+        if is_more_data(&result) {
+            return Ok(None);
+        }
+        result?;
+
+        Ok(Some(surfaceOut))
+    }
+
+    // Encodes a VPP output surface, adding the packets of the frames that
+    // completed to `packets`.
+    fn encode_vpp_output(
+        &mut self,
+        surfaceOut: &SurfaceLease,
+        control: &FrameControl,
+        packets: &mut Vec<Packet>,
+    ) -> Result<()> {
+        control.set(&mut self.enc_ctrls[surfaceOut.index()]);
+
+        if self.submit_surface(Some(surfaceOut))? {
+            self.sync_pending(self.pipeline_depth - 1, packets)?;
+        }
+        Ok(())
+    }
+
+    // Leases a surface of `pool`. The frames in flight hold theirs until
+    // synchronized, so while every surface is in use the oldest are waited
+    // for, adding their packets to `packets`.
+    fn lease_surface(
+        &mut self,
+        pool: fn(&mut Encoder) -> &mut SurfacePool,
+        packets: &mut Vec<Packet>,
+    ) -> Result<SurfaceLease> {
+        loop {
+            if let Some(lease) = pool(self).lease() {
+                return Ok(lease);
+            }
+            match self.sync_oldest()? {
+                Some(packet) => packets.push(packet),
+                None => return Err(Error::NoFreeSurface),
+            }
+        }
+    }
+
+    // Submits the given surface to the encoder, or drains it when there is
+    // none, leaving the frame in flight. Returns false when the encoder needs
+    // more data.
+    fn submit_surface(&mut self, surface: Option<&SurfaceLease>) -> Result<bool> {
+        let bitstreamSize = self.bitstream_size;
+        let mut bitstream = self
+            .bitstreams
+            .pop()
+            .unwrap_or_else(|| Bitstream::new(bitstreamSize));
+
+        let ctrls = &self.enc_ctrls;
+        let mut syncp_enc: mfxSyncPoint = ptr::null();
+        let result = loop {
+            let result = self.session.encode_frame_async(
+                surface.map(|s| &ctrls[s.index()]),
+                surface.map(SurfaceLease::surface),
+                &mut bitstream.bs,
+                &mut syncp_enc,
+            );
+            match result {
+                Ok(Some(MfxWarning::DeviceBusy)) => thread::sleep(DEVICE_BUSY_WAIT),
+                _ => break result,
+            }
+        };
+        if is_more_data(&result) {
+            self.bitstreams.push(bitstream);
+            return Ok(false);
+        }
+        result?;
+
+        if syncp_enc.is_null() {
+            self.bitstreams.push(bitstream);
+            return Ok(false);
+        }
+        self.pending.push_back(PendingFrame {
+            syncp: syncp_enc,
+            bitstream,
+        });
+        Ok(true)
+    }
+
+    // Waits for the oldest frames until at most `in_flight` are left, adding
+    // their packets to `packets`.
+    fn sync_pending(&mut self, in_flight: usize, packets: &mut Vec<Packet>) -> Result<()> {
+        while self.pending.len() > in_flight {
+            packets.extend(self.sync_oldest()?);
+        }
+        Ok(())
+    }
+
+    // Waits for the oldest frame in flight and returns its packet, or None
+    // when there is none.
+    fn sync_oldest(&mut self) -> Result<Option<Packet>> {
+        // the frame stays in flight, its bitstream untouched, if the wait fails
+        let syncp = match self.pending.front() {
+            Some(frame) => frame.syncp,
+            None => return Ok(None),
+        };
+        self.session.sync_operation(syncp, SYNC_TIMEOUT)?;
+
+        let mut frame = self.pending.pop_front().unwrap();
+        let packet = ReadBitStreamFrame(&mut frame.bitstream.bs);
+        self.bitstreams.push(frame.bitstream);
+        Ok(Some(packet))
+    }
+}
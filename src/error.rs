@@ -0,0 +1,300 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use crate::sys::*;
+
+/// Negative `mfxStatus` codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MfxError {
+    Unknown,
+    NullPtr,
+    Unsupported,
+    MemoryAlloc,
+    NotEnoughBuffer,
+    InvalidHandle,
+    LockMemory,
+    NotInitialized,
+    NotFound,
+    MoreData,
+    MoreSurface,
+    Aborted,
+    DeviceLost,
+    IncompatibleVideoParam,
+    InvalidVideoParam,
+    UndefinedBehavior,
+    DeviceFailed,
+    MoreBitstream,
+    IncompatibleAudioParam,
+    InvalidAudioParam,
+    GpuHang,
+    ReallocSurface,
+    ResourceMapped,
+    NotImplemented,
+    /// Status code not known to this crate.
+    Other(mfxStatus),
+}
+
+/// Positive `mfxStatus` codes: the call succeeded but something is worth
+/// reporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MfxWarning {
+    InExecution,
+    DeviceBusy,
+    VideoParamChanged,
+    PartialAcceleration,
+    IncompatibleVideoParam,
+    ValueNotChanged,
+    OutOfRange,
+    FilterSkipped,
+    IncompatibleAudioParam,
+    PartialOutput,
+    AllocTimeoutExpired,
+    /// Status code not known to this crate.
+    Other(mfxStatus),
+}
+
+/// Errors returned by the encoder.
+#[derive(Debug)]
+pub enum Error {
+    /// A Media SDK call failed.
+    Mfx {
+        function: &'static str,
+        error: MfxError,
+    },
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// The configuration cannot be encoded.
+    Config(String),
+    /// An input frame does not have the size the encoder was configured for.
+    InvalidFrame { expected: usize, actual: usize },
+    /// Every surface of a pool is still in use by the driver.
+    NoFreeSurface,
+    /// No Media SDK or oneVPL runtime library could be loaded.
+    RuntimeNotFound(String),
+    /// The runtime library lacks an entry point.
+    MissingSymbol(&'static str),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl MfxError {
+    pub fn from_status(sts: mfxStatus) -> MfxError {
+        match sts {
+            MFX_ERR_UNKNOWN => MfxError::Unknown,
+            MFX_ERR_NULL_PTR => MfxError::NullPtr,
+            MFX_ERR_UNSUPPORTED => MfxError::Unsupported,
+            MFX_ERR_MEMORY_ALLOC => MfxError::MemoryAlloc,
+            MFX_ERR_NOT_ENOUGH_BUFFER => MfxError::NotEnoughBuffer,
+            MFX_ERR_INVALID_HANDLE => MfxError::InvalidHandle,
+            MFX_ERR_LOCK_MEMORY => MfxError::LockMemory,
+            MFX_ERR_NOT_INITIALIZED => MfxError::NotInitialized,
+            MFX_ERR_NOT_FOUND => MfxError::NotFound,
+            MFX_ERR_MORE_DATA => MfxError::MoreData,
+            MFX_ERR_MORE_SURFACE => MfxError::MoreSurface,
+            MFX_ERR_ABORTED => MfxError::Aborted,
+            MFX_ERR_DEVICE_LOST => MfxError::DeviceLost,
+            MFX_ERR_INCOMPATIBLE_VIDEO_PARAM => MfxError::IncompatibleVideoParam,
+            MFX_ERR_INVALID_VIDEO_PARAM => MfxError::InvalidVideoParam,
+            MFX_ERR_UNDEFINED_BEHAVIOR => MfxError::UndefinedBehavior,
+            MFX_ERR_DEVICE_FAILED => MfxError::DeviceFailed,
+            MFX_ERR_MORE_BITSTREAM => MfxError::MoreBitstream,
+            MFX_ERR_INCOMPATIBLE_AUDIO_PARAM => MfxError::IncompatibleAudioParam,
+            MFX_ERR_INVALID_AUDIO_PARAM => MfxError::InvalidAudioParam,
+            MFX_ERR_GPU_HANG => MfxError::GpuHang,
+            MFX_ERR_REALLOC_SURFACE => MfxError::ReallocSurface,
+            MFX_ERR_RESOURCE_MAPPED => MfxError::ResourceMapped,
+            MFX_ERR_NOT_IMPLEMENTED => MfxError::NotImplemented,
+            other => MfxError::Other(other),
+        }
+    }
+
+    pub fn status(self) -> mfxStatus {
+        match self {
+            MfxError::Unknown => MFX_ERR_UNKNOWN,
+            MfxError::NullPtr => MFX_ERR_NULL_PTR,
+            MfxError::Unsupported => MFX_ERR_UNSUPPORTED,
+            MfxError::MemoryAlloc => MFX_ERR_MEMORY_ALLOC,
+            MfxError::NotEnoughBuffer => MFX_ERR_NOT_ENOUGH_BUFFER,
+            MfxError::InvalidHandle => MFX_ERR_INVALID_HANDLE,
+            MfxError::LockMemory => MFX_ERR_LOCK_MEMORY,
+            MfxError::NotInitialized => MFX_ERR_NOT_INITIALIZED,
+            MfxError::NotFound => MFX_ERR_NOT_FOUND,
+            MfxError::MoreData => MFX_ERR_MORE_DATA,
+            MfxError::MoreSurface => MFX_ERR_MORE_SURFACE,
+            MfxError::Aborted => MFX_ERR_ABORTED,
+            MfxError::DeviceLost => MFX_ERR_DEVICE_LOST,
+            MfxError::IncompatibleVideoParam => MFX_ERR_INCOMPATIBLE_VIDEO_PARAM,
+            MfxError::InvalidVideoParam => MFX_ERR_INVALID_VIDEO_PARAM,
+            MfxError::UndefinedBehavior => MFX_ERR_UNDEFINED_BEHAVIOR,
+            MfxError::DeviceFailed => MFX_ERR_DEVICE_FAILED,
+            MfxError::MoreBitstream => MFX_ERR_MORE_BITSTREAM,
+            MfxError::IncompatibleAudioParam => MFX_ERR_INCOMPATIBLE_AUDIO_PARAM,
+            MfxError::InvalidAudioParam => MFX_ERR_INVALID_AUDIO_PARAM,
+            MfxError::GpuHang => MFX_ERR_GPU_HANG,
+            MfxError::ReallocSurface => MFX_ERR_REALLOC_SURFACE,
+            MfxError::ResourceMapped => MFX_ERR_RESOURCE_MAPPED,
+            MfxError::NotImplemented => MFX_ERR_NOT_IMPLEMENTED,
+            MfxError::Other(sts) => sts,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            MfxError::Unknown => "unknown error",
+            MfxError::NullPtr => "null pointer",
+            MfxError::Unsupported => "unsupported feature or parameter",
+            MfxError::MemoryAlloc => "memory allocation failed",
+            MfxError::NotEnoughBuffer => "insufficient buffer",
+            MfxError::InvalidHandle => "invalid handle",
+            MfxError::LockMemory => "failed to lock memory block",
+            MfxError::NotInitialized => "component not initialized",
+            MfxError::NotFound => "object not found",
+            MfxError::MoreData => "more input data expected",
+            MfxError::MoreSurface => "more output surfaces expected",
+            MfxError::Aborted => "operation aborted",
+            MfxError::DeviceLost => "hardware device lost",
+            MfxError::IncompatibleVideoParam => "incompatible video parameters",
+            MfxError::InvalidVideoParam => "invalid video parameters",
+            MfxError::UndefinedBehavior => "undefined behavior",
+            MfxError::DeviceFailed => "device operation failed",
+            MfxError::MoreBitstream => "more bitstream buffers expected",
+            MfxError::IncompatibleAudioParam => "incompatible audio parameters",
+            MfxError::InvalidAudioParam => "invalid audio parameters",
+            MfxError::GpuHang => "GPU hang",
+            MfxError::ReallocSurface => "surface reallocation required",
+            MfxError::ResourceMapped => "resource is mapped",
+            MfxError::NotImplemented => "not implemented",
+            MfxError::Other(_) => "unrecognized status",
+        }
+    }
+}
+
+impl MfxWarning {
+    pub fn from_status(sts: mfxStatus) -> MfxWarning {
+        match sts {
+            MFX_WRN_IN_EXECUTION => MfxWarning::InExecution,
+            MFX_WRN_DEVICE_BUSY => MfxWarning::DeviceBusy,
+            MFX_WRN_VIDEO_PARAM_CHANGED => MfxWarning::VideoParamChanged,
+            MFX_WRN_PARTIAL_ACCELERATION => MfxWarning::PartialAcceleration,
+            MFX_WRN_INCOMPATIBLE_VIDEO_PARAM => MfxWarning::IncompatibleVideoParam,
+            MFX_WRN_VALUE_NOT_CHANGED => MfxWarning::ValueNotChanged,
+            MFX_WRN_OUT_OF_RANGE => MfxWarning::OutOfRange,
+            MFX_WRN_FILTER_SKIPPED => MfxWarning::FilterSkipped,
+            MFX_WRN_INCOMPATIBLE_AUDIO_PARAM => MfxWarning::IncompatibleAudioParam,
+            MFX_ERR_NONE_PARTIAL_OUTPUT => MfxWarning::PartialOutput,
+            MFX_WRN_ALLOC_TIMEOUT_EXPIRED => MfxWarning::AllocTimeoutExpired,
+            other => MfxWarning::Other(other),
+        }
+    }
+
+    pub fn status(self) -> mfxStatus {
+        match self {
+            MfxWarning::InExecution => MFX_WRN_IN_EXECUTION,
+            MfxWarning::DeviceBusy => MFX_WRN_DEVICE_BUSY,
+            MfxWarning::VideoParamChanged => MFX_WRN_VIDEO_PARAM_CHANGED,
+            MfxWarning::PartialAcceleration => MFX_WRN_PARTIAL_ACCELERATION,
+            MfxWarning::IncompatibleVideoParam => MFX_WRN_INCOMPATIBLE_VIDEO_PARAM,
+            MfxWarning::ValueNotChanged => MFX_WRN_VALUE_NOT_CHANGED,
+            MfxWarning::OutOfRange => MFX_WRN_OUT_OF_RANGE,
+            MfxWarning::FilterSkipped => MFX_WRN_FILTER_SKIPPED,
+            MfxWarning::IncompatibleAudioParam => MFX_WRN_INCOMPATIBLE_AUDIO_PARAM,
+            MfxWarning::PartialOutput => MFX_ERR_NONE_PARTIAL_OUTPUT,
+            MfxWarning::AllocTimeoutExpired => MFX_WRN_ALLOC_TIMEOUT_EXPIRED,
+            MfxWarning::Other(sts) => sts,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            MfxWarning::InExecution => "previous asynchronous operation still executing",
+            MfxWarning::DeviceBusy => "hardware device busy",
+            MfxWarning::VideoParamChanged => "video parameters changed",
+            MfxWarning::PartialAcceleration => "software fallback used",
+            MfxWarning::IncompatibleVideoParam => "incompatible video parameters corrected",
+            MfxWarning::ValueNotChanged => "value saturated",
+            MfxWarning::OutOfRange => "value out of range",
+            MfxWarning::FilterSkipped => "filter skipped",
+            MfxWarning::IncompatibleAudioParam => "incompatible audio parameters corrected",
+            MfxWarning::PartialOutput => "frame partially output",
+            MfxWarning::AllocTimeoutExpired => "surface allocation timed out",
+            MfxWarning::Other(_) => "unrecognized status",
+        }
+    }
+}
+
+/// Converts the status returned by `function` into a `Result`, keeping any
+/// warning for the caller to act on.
+pub(crate) fn check(function: &'static str, sts: mfxStatus) -> Result<Option<MfxWarning>> {
+    if sts < MFX_ERR_NONE {
+        return Err(Error::Mfx {
+            function,
+            error: MfxError::from_status(sts),
+        });
+    }
+    if sts > MFX_ERR_NONE {
+        return Ok(Some(MfxWarning::from_status(sts)));
+    }
+    Ok(None)
+}
+
+impl Error {
+    /// Media SDK error carried by this error, if any.
+    pub fn mfx_error(&self) -> Option<MfxError> {
+        match *self {
+            Error::Mfx { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MfxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.status())
+    }
+}
+
+impl fmt::Display for MfxWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.status())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mfx { function, error } => write!(f, "{} failed: {}", function, error),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Config(ref msg) => write!(f, "invalid configuration: {}", msg),
+            Error::InvalidFrame { expected, actual } => write!(
+                f,
+                "invalid frame size: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Error::NoFreeSurface => write!(f, "no free surface available"),
+            Error::RuntimeNotFound(ref msg) => write!(f, "runtime not found: {}", msg),
+            Error::MissingSymbol(name) => write!(f, "runtime does not export {}", name),
+        }
+    }
+}
+
+impl error::Error for MfxError {}
+
+impl error::Error for MfxWarning {}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Mfx { ref error, .. } => Some(error),
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
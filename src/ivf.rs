@@ -0,0 +1,76 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::encoder::{Codec, EncoderConfig, FrameRate, Packet};
+
+const HEADER_SIZE: u16 = 32;
+// offset of the frame count in the file header
+const FRAME_COUNT_OFFSET: u64 = 24;
+
+/// Writes packets to an IVF file, the container libvpx and libaom use for
+/// raw VP8, VP9 and AV1 streams.
+///
+/// The time base is the inverse of the frame rate, so each frame timestamp
+/// is its frame number. The frame count in the file header is only written
+/// by [`IvfWriter::finish`].
+pub struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    frame_rate: FrameRate,
+    frames: u32,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// Writes the file header for the stream `config` describes.
+    pub fn new(mut writer: W, config: &EncoderConfig) -> io::Result<IvfWriter<W>> {
+        let fourcc: &[u8; 4] = match config.codec {
+            Codec::Avc => b"H264",
+            Codec::Hevc => b"H265",
+            Codec::Vp9 => b"VP90",
+            Codec::Av1 => b"AV01",
+            Codec::Jpeg => b"MJPG",
+        };
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"DKIF");
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(fourcc);
+        header.extend_from_slice(&(config.width as u16).to_le_bytes());
+        header.extend_from_slice(&(config.height as u16).to_le_bytes());
+        // time base denominator and numerator
+        header.extend_from_slice(&config.frame_rate.num.to_le_bytes());
+        header.extend_from_slice(&config.frame_rate.den.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(IvfWriter {
+            writer,
+            frame_rate: config.frame_rate,
+            frames: 0,
+        })
+    }
+
+    /// Appends one frame holding `packet`.
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        // back from the 90 kHz clock to frame numbers, rounding to nearest
+        let num = self.frame_rate.num as u64;
+        let den = self.frame_rate.den as u64;
+        let pts = (packet.timestamp * num + 45000 * den) / (90000 * den);
+
+        self.writer
+            .write_all(&(packet.data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&pts.to_le_bytes())?;
+        self.writer.write_all(&packet.data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Records the frame count in the file header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.writer.write_all(&self.frames.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
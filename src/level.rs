@@ -0,0 +1,137 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::encoder::{FrameRate, Profile};
+use crate::error::{Error, Result};
+use crate::sys::*;
+
+// H.264 Table A-1: level, MaxMBPS, MaxFS in macroblocks, and MaxBR and
+// MaxCPB in units of cpbBrVclFactor bits/s and bits. MaxDpbMbs is left to
+// the runtime, which picks the number of reference frames.
+const AVC_LEVEL_LIMITS: &[(mfxU16, u64, u64, u64, u64)] = &[
+    (MFX_LEVEL_AVC_1, 1485, 99, 64, 175),
+    (MFX_LEVEL_AVC_1b, 1485, 99, 128, 350),
+    (MFX_LEVEL_AVC_11, 3000, 396, 192, 500),
+    (MFX_LEVEL_AVC_12, 6000, 396, 384, 1000),
+    (MFX_LEVEL_AVC_13, 11880, 396, 768, 2000),
+    (MFX_LEVEL_AVC_2, 11880, 396, 2000, 2000),
+    (MFX_LEVEL_AVC_21, 19800, 792, 4000, 4000),
+    (MFX_LEVEL_AVC_22, 20250, 1620, 4000, 4000),
+    (MFX_LEVEL_AVC_3, 40500, 1620, 10000, 10000),
+    (MFX_LEVEL_AVC_31, 108000, 3600, 14000, 14000),
+    (MFX_LEVEL_AVC_32, 216000, 5120, 20000, 20000),
+    (MFX_LEVEL_AVC_4, 245760, 8192, 20000, 25000),
+    (MFX_LEVEL_AVC_41, 245760, 8192, 50000, 62500),
+    (MFX_LEVEL_AVC_42, 522240, 8704, 50000, 62500),
+    (MFX_LEVEL_AVC_5, 589824, 22080, 135000, 135000),
+    (MFX_LEVEL_AVC_51, 983040, 36864, 240000, 240000),
+    (MFX_LEVEL_AVC_52, 2073600, 36864, 240000, 240000),
+    (MFX_LEVEL_AVC_6, 4177920, 139264, 240000, 240000),
+    (MFX_LEVEL_AVC_61, 8355840, 139264, 480000, 480000),
+    (MFX_LEVEL_AVC_62, 16711680, 139264, 800000, 800000),
+];
+
+/// H.264 level, such as `"4.1".parse()` or `"1b".parse()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level(mfxU16);
+
+/// What a stream asks of its level.
+pub(crate) struct LevelRequirements {
+    pub width: usize,
+    pub height: usize,
+    pub frame_rate: FrameRate,
+    /// Peak bitrate in kbps, 0 if the rate control method has none.
+    pub bitrate: u32,
+    /// HRD buffer size in KB, 0 for the runtime default.
+    pub buffer_size: u32,
+    pub profile: Option<Profile>,
+}
+
+impl Level {
+    /// `CodecLevel` value, the `level_idc` of the SPS but for level 1b.
+    pub fn value(self) -> mfxU16 {
+        self.0
+    }
+
+    /// Lowest level `requirements` fit in.
+    pub(crate) fn lowest(requirements: &LevelRequirements) -> Result<Level> {
+        AVC_LEVEL_LIMITS
+            .iter()
+            .map(|&(level, ..)| Level(level))
+            .find(|level| level.check(requirements).is_ok())
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "{}x{} at {}/{} fps exceeds every H.264 level",
+                    requirements.width,
+                    requirements.height,
+                    requirements.frame_rate.num,
+                    requirements.frame_rate.den
+                ))
+            })
+    }
+
+    /// Checks `requirements` against the Table A-1 limits of the level.
+    pub(crate) fn check(self, requirements: &LevelRequirements) -> Result<()> {
+        let &(_, max_mbps, max_fs, max_br, max_cpb) = AVC_LEVEL_LIMITS
+            .iter()
+            .find(|limits| limits.0 == self.0)
+            .unwrap();
+        let exceeds = |what: String| Err(Error::Config(format!("{} exceeds level {}", what, self)));
+
+        let width_mbs = requirements.width.div_ceil(16) as u64;
+        let height_mbs = requirements.height.div_ceil(16) as u64;
+        let frame_size = width_mbs * height_mbs;
+        // the frame may be no wider or taller than Sqrt(MaxFS * 8)
+        if frame_size > max_fs
+            || width_mbs * width_mbs > 8 * max_fs
+            || height_mbs * height_mbs > 8 * max_fs
+        {
+            return exceeds(format!("{}x{}", requirements.width, requirements.height));
+        }
+        let FrameRate { num, den } = requirements.frame_rate;
+        if frame_size * num as u64 > max_mbps * den as u64 {
+            return exceeds(format!(
+                "{}x{} at {}/{} fps",
+                requirements.width, requirements.height, num, den
+            ));
+        }
+
+        // Table A-2, cpbBrVclFactor
+        let factor = match requirements.profile {
+            Some(Profile::High) => 1250,
+            Some(Profile::High10) => 3000,
+            _ => 1000,
+        };
+        if requirements.bitrate as u64 * 1000 > max_br * factor {
+            return exceeds(format!("{} kbps", requirements.bitrate));
+        }
+        if requirements.buffer_size as u64 * 8000 > max_cpb * factor {
+            return exceeds(format!("a {} KB buffer", requirements.buffer_size));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            level if level == MFX_LEVEL_AVC_1b => write!(f, "1b"),
+            level if level % 10 == 0 => write!(f, "{}", level / 10),
+            level => write!(f, "{}.{}", level / 10, level % 10),
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Level> {
+        // 4.0 is level 4
+        let name = s.strip_suffix(".0").unwrap_or(s);
+        AVC_LEVEL_LIMITS
+            .iter()
+            .map(|&(level, ..)| Level(level))
+            .find(|level| level.to_string() == name)
+            .ok_or_else(|| Error::Config(format!("unknown H.264 level '{}'", s)))
+    }
+}
@@ -0,0 +1,40 @@
+//! Quick Sync video encoder.
+//!
+//! The raw Media SDK bindings live in [`sys`]; [`Encoder`] wraps them into a
+//! safe frame-in, packets-out API. Every runtime call goes through a
+//! [`Backend`], so the pipeline can run against [`MockBackend`] on machines
+//! without Quick Sync hardware.
+
+extern crate libc;
+
+mod backend;
+mod caps;
+mod encoder;
+mod error;
+mod ivf;
+mod level;
+mod library;
+mod mock;
+mod options;
+mod session;
+mod surface;
+pub mod sys;
+mod webm;
+
+pub use backend::{Backend, SdkBackend};
+pub use caps::{
+    query_capabilities, query_capabilities_with_backend, EncoderCaps, ImplementationCaps,
+};
+pub use encoder::{
+    BitrateParams, Codec, Encoder, EncoderConfig, ForcedFrame, FrameControl, FrameRate,
+    InputFormat, KeyframeInterval, Packet, Profile, RateControl, Reconfiguration, Vp9Segment,
+    Vp9Segmentation,
+};
+pub use error::{Error, MfxError, MfxWarning, Result};
+pub use ivf::IvfWriter;
+pub use level::Level;
+pub use mock::MockBackend;
+pub use options::{
+    CodingOption, CodingOption2, CodingOption3, SkipFrame, Trellis, WeightedPrediction,
+};
+pub use webm::WebmWriter;
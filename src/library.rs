@@ -0,0 +1,94 @@
+use std::ffi::CString;
+
+use libc::c_void;
+
+/// Shared library loaded at runtime, unloaded on drop.
+pub(crate) struct Library {
+    handle: *mut c_void,
+}
+
+// Library handles and the symbols resolved from them may be used from any
+// thread.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+#[cfg(unix)]
+mod platform {
+    use std::ffi::CStr;
+
+    use libc::{c_char, c_void};
+
+    pub unsafe fn open(name: *const c_char) -> Result<*mut c_void, String> {
+        let handle = libc::dlopen(name, libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            let error = libc::dlerror();
+            if error.is_null() {
+                return Err("unknown error".to_string());
+            }
+            return Err(CStr::from_ptr(error).to_string_lossy().into_owned());
+        }
+        Ok(handle)
+    }
+
+    pub unsafe fn symbol(handle: *mut c_void, name: *const c_char) -> *mut c_void {
+        libc::dlsym(handle, name)
+    }
+
+    pub unsafe fn close(handle: *mut c_void) {
+        libc::dlclose(handle);
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use libc::{c_char, c_int, c_void};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryA(name: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+        fn FreeLibrary(module: *mut c_void) -> c_int;
+        fn GetLastError() -> u32;
+    }
+
+    pub unsafe fn open(name: *const c_char) -> Result<*mut c_void, String> {
+        let handle = LoadLibraryA(name);
+        if handle.is_null() {
+            return Err(format!("error {}", GetLastError()));
+        }
+        Ok(handle)
+    }
+
+    pub unsafe fn symbol(handle: *mut c_void, name: *const c_char) -> *mut c_void {
+        GetProcAddress(handle, name)
+    }
+
+    pub unsafe fn close(handle: *mut c_void) {
+        FreeLibrary(handle);
+    }
+}
+
+impl Library {
+    pub fn open(name: &str) -> Result<Library, String> {
+        let c_name = CString::new(name).map_err(|e| e.to_string())?;
+        let handle = unsafe { platform::open(c_name.as_ptr())? };
+        Ok(Library { handle })
+    }
+
+    /// Looks up a NUL-terminated symbol name.
+    pub fn symbol(&self, name: &str) -> Option<*mut c_void> {
+        debug_assert!(name.ends_with('\0'));
+        let symbol = unsafe { platform::symbol(self.handle, name.as_ptr() as *const _) };
+        if symbol.is_null() {
+            None
+        } else {
+            Some(symbol)
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { platform::close(self.handle) };
+    }
+}
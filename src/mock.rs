@@ -0,0 +1,1612 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::CStr;
+use std::ops::RangeInclusive;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use crate::backend::Backend;
+use crate::sys::*;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+const API_VERSION: mfxVersion = mfxVersion::new(2, 9);
+const LEGACY_API_VERSION: mfxVersion = mfxVersion::new(1, 35);
+const CODECS: &[mfxU32] = &[
+    MFX_CODEC_AVC,
+    MFX_CODEC_HEVC,
+    MFX_CODEC_VP9,
+    MFX_CODEC_AV1,
+    MFX_CODEC_JPEG,
+];
+const AVC_MAX_SIZE: mfxU16 = 4096;
+const AVC_PROFILES: &[mfxU16] = &[
+    MFX_PROFILE_UNKNOWN,
+    MFX_PROFILE_AVC_BASELINE,
+    MFX_PROFILE_AVC_CONSTRAINED_BASELINE,
+    MFX_PROFILE_AVC_MAIN,
+    MFX_PROFILE_AVC_HIGH,
+];
+const HEVC_MAX_SIZE: mfxU16 = 8192;
+// profile and the surface formats within its bit depth and chroma format
+const HEVC_PROFILES: &[(mfxU16, &[mfxU32])] = &[
+    (MFX_PROFILE_HEVC_MAIN, &[MFX_FOURCC_NV12]),
+    (MFX_PROFILE_HEVC_MAIN10, &[MFX_FOURCC_NV12, MFX_FOURCC_P010]),
+    (
+        MFX_PROFILE_HEVC_REXT,
+        &[MFX_FOURCC_NV12, MFX_FOURCC_P010, MFX_FOURCC_AYUV],
+    ),
+];
+const VP9_MAX_SIZE: mfxU16 = 8192;
+const VP9_PROFILES: &[(mfxU16, &[mfxU32])] = &[
+    (MFX_PROFILE_VP9_0, &[MFX_FOURCC_NV12]),
+    (MFX_PROFILE_VP9_1, &[MFX_FOURCC_NV12, MFX_FOURCC_AYUV]),
+    (MFX_PROFILE_VP9_2, &[MFX_FOURCC_NV12, MFX_FOURCC_P010]),
+    (
+        MFX_PROFILE_VP9_3,
+        &[MFX_FOURCC_NV12, MFX_FOURCC_P010, MFX_FOURCC_AYUV],
+    ),
+];
+// narrowest VP9 tile column
+const VP9_MIN_TILE_WIDTH: mfxU16 = 256;
+const VP9_MAX_TILE_ROWS: mfxU16 = 4;
+const VP9_MAX_SEGMENTS: mfxU16 = 8;
+const AV1_MAX_SIZE: mfxU16 = 8192;
+const AV1_PROFILES: &[(mfxU16, &[mfxU32])] = &[
+    (MFX_PROFILE_AV1_MAIN, &[MFX_FOURCC_NV12, MFX_FOURCC_P010]),
+    (
+        MFX_PROFILE_AV1_HIGH,
+        &[MFX_FOURCC_NV12, MFX_FOURCC_P010, MFX_FOURCC_AYUV],
+    ),
+];
+// AV1 superblock size, at least one per tile
+const AV1_SUPERBLOCK_SIZE: mfxU16 = 64;
+const JPEG_MAX_SIZE: mfxU16 = 16384;
+const JPEG_FOURCCS: &[mfxU32] = &[MFX_FOURCC_NV12, MFX_FOURCC_YUY2, MFX_FOURCC_RGB4];
+const RATE_CONTROL_METHODS: &[mfxU16] = &[
+    MFX_RATECONTROL_CBR,
+    MFX_RATECONTROL_VBR,
+    MFX_RATECONTROL_CQP,
+    MFX_RATECONTROL_AVBR,
+    MFX_RATECONTROL_LA,
+    MFX_RATECONTROL_ICQ,
+    MFX_RATECONTROL_LA_ICQ,
+    MFX_RATECONTROL_QVBR,
+];
+// methods of the LowPower encoders, without look-ahead or AVBR
+const LOW_POWER_RATE_CONTROL_METHODS: &[mfxU16] = &[
+    MFX_RATECONTROL_CBR,
+    MFX_RATECONTROL_VBR,
+    MFX_RATECONTROL_CQP,
+    MFX_RATECONTROL_ICQ,
+    MFX_RATECONTROL_QVBR,
+];
+// H.264 and HEVC quantizers and qualities, VP9 and AV1 quantizer indices
+const MAX_QP: mfxU16 = 51;
+const MAX_QINDEX: mfxU16 = 255;
+const LOOK_AHEAD_DEPTHS: RangeInclusive<mfxU16> = 10..=100;
+
+/// Deterministic in-process [`Backend`] needing neither libmfx nor a GPU.
+///
+/// VPP converts each YUV 4:2:0 input surface to NV12, P010 or AYUV. ENCODE
+/// holds back `encode_delay` frames, answering `MFX_ERR_MORE_DATA` until its
+/// queue is full, then emits one synthetic access unit per frame: parameter
+/// sets and an IDR slice for IDR frames, a non-IDR slice otherwise, each
+/// slice carrying the frame number and a checksum of the 8 most significant
+/// bits of luma, after an access unit delimiter if `AUDelimiter` is on.
+/// `mfxEncodeCtrl` forces I or IDR frames, and with `SkipFrame` turns
+/// P-frames into a dummy slice carrying only the frame number, or into
+/// nothing. The first frame and every `GopPicSize`th frame are I-frames, IDR
+/// ones as `IdrInterval` says; there are no B-frames. H.264 and HEVC access
+/// units are Annex B, AV1 temporal units are a temporal delimiter, sequence
+/// header and frame OBUs, and VP9 frames are a frame marker byte, followed on
+/// key frames by the sync code and the stream parameters. Unless
+/// `WriteIVFHeaders` is off, VP9 frames come with IVF headers, as they do
+/// from the Media SDK. JPEG images have no encode delay and are made of SOI,
+/// a DQT segment holding only the quality, SOF0 with the crop size, SOS with
+/// the frame number and checksum as entropy coded data, and EOI. The SPS,
+/// sequence header or VP9 stream parameter payload is the profile and level
+/// bytes followed by the crop size and the VUI `num_units_in_tick` and
+/// `time_scale`, all big-endian. The bitstream timestamp is the one of the
+/// encoded surface. As with the real runtime, surfaces stay locked and
+/// bitstreams are only written once the returned sync point is synchronized,
+/// and a bitstream still in flight cannot take another frame. A VPP output
+/// surface submitted to ENCODE is converted right then, and its VPP sync
+/// point is no longer valid.
+///
+/// `MFXVideoENCODE_Reset` drops the frames held back and applies new
+/// parameters in place, starting a new sequence on request or with a new
+/// frame size, but answers `MFX_ERR_INCOMPATIBLE_VIDEO_PARAM` to a frame
+/// larger than at init or to a new surface format, profile, engine, rate
+/// control method or `GopRefDist`. It fails outright with
+/// [`MockBackend::with_failing_encode_reset`].
+///
+/// Sessions are created through a oneVPL dispatcher exposing a single working
+/// hardware implementation, followed by one that cannot create sessions with
+/// [`MockBackend::with_broken_implementation`], unless the mock is made to
+/// look like a Media SDK 1.x runtime with [`MockBackend::legacy`], where the
+/// HEVC encoder is a plugin to load first and there is no AV1 encoder. Either
+/// way there is one adapter, whose encoders accept quantizers and ICQ
+/// qualities from 1 to 51, or quantizer indices up to 255 for VP9 and
+/// AV1, and
+///
+/// * for H.264 and HEVC, the `mfxExtCodingOption`, `mfxExtCodingOption2` and
+///   `mfxExtCodingOption3` buffers, reporting the options left to the
+///   runtime as off but for CABAC, HRD conformance, VUI timing and HEVC GPB;
+/// * for H.264, NV12 input up to 4096x4096 with the Baseline, Main and High
+///   profiles, every rate control method but VCM and LA_HRD, look-ahead
+///   depths of 10 to 100, and no LowPower mode;
+/// * for HEVC, VP9 and AV1, the CBR, VBR, CQP, ICQ and QVBR methods;
+/// * for HEVC, NV12 input with the Main profile, NV12 and P010 with Main 10
+///   and NV12, P010 and AYUV with the range extensions, up to 8192x8192, in
+///   LowPower mode only;
+/// * for VP9, NV12 input for profile 0, with AYUV for profile 1, P010 for
+///   profile 2 and both for profile 3, up to 8192x8192 with tile columns at
+///   least 256 pixels wide and up to 8 segments, in LowPower mode only;
+/// * for AV1, NV12 and P010 input with the Main profile and NV12, P010 and
+///   AYUV with High, up to 8192x8192 and one tile per 64x64 superblock at
+///   most, in LowPower mode only;
+/// * for JPEG, NV12, YUY2 and RGB4 input at quality 1 to 100 with the
+///   Baseline profile, up to 16384x16384, and no LowPower mode.
+pub struct MockBackend {
+    encode_delay: usize,
+    dispatcher: bool,
+    // a second dispatcher implementation, which cannot create sessions
+    broken_implementation: bool,
+    failing_encode_reset: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_session: usize,
+    sessions: HashMap<usize, MockSession>,
+    next_handle: usize,
+    loaders: HashMap<usize, MockLoader>,
+    // config handle to the loader it was created on
+    configs: HashMap<usize, usize>,
+}
+
+#[derive(Default)]
+struct MockLoader {
+    // config handle, property name and value
+    filters: Vec<(usize, Vec<u8>, mfxU32)>,
+    descriptions: usize,
+}
+
+// The raw pointers held in the state are only dereferenced inside backend
+// calls, which the caller guarantees to be valid for as long as the
+// corresponding surfaces and bitstreams are in use.
+unsafe impl Send for State {}
+
+#[derive(Default)]
+struct MockSession {
+    // created through the dispatcher, with every encoder built in
+    onevpl: bool,
+    plugins: Vec<mfxPluginUID>,
+    vpp: Option<mfxVideoParam>,
+    encode: Option<MockEncode>,
+    next_sync: usize,
+    tasks: HashMap<usize, Task>,
+    // surfaces VPP has written to, and how many ENCODE took
+    vpp_outputs: HashSet<usize>,
+    encoded_vpp_outputs: usize,
+}
+
+struct MockEncode {
+    par: mfxVideoParam,
+    // coding options in effect, none left unknown
+    coding_option: mfxExtCodingOption,
+    coding_option2: mfxExtCodingOption2,
+    coding_option3: mfxExtCodingOption3,
+    ivf_headers: bool,
+    // frame size allocated at init, which a reset may not exceed
+    max_width: mfxU16,
+    max_height: mfxU16,
+    // surfaces held back, with the control they were submitted with
+    queue: VecDeque<(*mut mfxFrameSurface1, mfxEncodeCtrl)>,
+    frame_order: u32,
+    // frame_order of the first frame of the sequence
+    sequence_start: u32,
+}
+
+enum Task {
+    Vpp {
+        input: *mut mfxFrameSurface1,
+        output: *mut mfxFrameSurface1,
+    },
+    Encode {
+        surface: *mut mfxFrameSurface1,
+        bs: *mut mfxBitstream,
+        au: Vec<u8>,
+        frame_type: mfxU16,
+    },
+}
+
+impl MockBackend {
+    /// Mock whose encoder buffers two frames before producing output.
+    pub fn new() -> MockBackend {
+        MockBackend::with_encode_delay(2)
+    }
+
+    /// Mock whose encoder buffers `encode_delay` frames before producing
+    /// output.
+    pub fn with_encode_delay(encode_delay: usize) -> MockBackend {
+        MockBackend {
+            encode_delay,
+            dispatcher: true,
+            broken_implementation: false,
+            failing_encode_reset: false,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Mock of a Media SDK 1.x runtime, which has no dispatcher and only
+    /// creates sessions through `MFXInit`.
+    pub fn legacy(mut self) -> MockBackend {
+        self.dispatcher = false;
+        self
+    }
+
+    /// Mock whose dispatcher enumerates a second implementation, on which
+    /// `MFXCreateSession` fails.
+    pub fn with_broken_implementation(mut self) -> MockBackend {
+        self.broken_implementation = true;
+        self
+    }
+
+    /// Mock whose `MFXVideoENCODE_Reset` fails with `MFX_ERR_DEVICE_FAILED`,
+    /// while `MFXVideoVPP_Reset` still succeeds.
+    pub fn with_failing_encode_reset(mut self) -> MockBackend {
+        self.failing_encode_reset = true;
+        self
+    }
+
+    /// Number of sessions initialized and not yet closed.
+    pub fn open_sessions(&self) -> usize {
+        self.state.lock().unwrap().sessions.len()
+    }
+
+    /// Number of dispatcher loaders created and not yet unloaded.
+    pub fn open_loaders(&self) -> usize {
+        self.state.lock().unwrap().loaders.len()
+    }
+
+    /// Number of frames ENCODE took in a surface VPP had written to, across
+    /// all open sessions. None if the application copies the VPP output to
+    /// surfaces of its own.
+    pub fn encoded_vpp_outputs(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.sessions.values().map(|s| s.encoded_vpp_outputs).sum()
+    }
+
+    /// Number of VPP and encode operations submitted and not yet
+    /// synchronized, across all sessions.
+    pub fn operations_in_flight(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.sessions.values().map(|s| s.tasks.len()).sum()
+    }
+
+    // JPEG images are independent, nothing to wait for
+    fn delay(&self, par: &mfxVideoParam) -> usize {
+        match unsafe { par.u.mfx.CodecId } {
+            MFX_CODEC_JPEG => 0,
+            _ => self.encode_delay,
+        }
+    }
+
+    fn with_session<F>(&self, session: *const mfxSession, f: F) -> mfxStatus
+    where
+        F: FnOnce(&mut MockSession) -> mfxStatus,
+    {
+        let mut state = self.state.lock().unwrap();
+        match state.sessions.get_mut(&(session as usize)) {
+            Some(s) => f(s),
+            None => MFX_ERR_INVALID_HANDLE,
+        }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> MockBackend {
+        MockBackend::new()
+    }
+}
+
+impl State {
+    fn new_session(&mut self, onevpl: bool) -> *mut mfxSession {
+        self.next_session += 1;
+        let session = MockSession {
+            onevpl,
+            ..MockSession::default()
+        };
+        self.sessions.insert(self.next_session, session);
+        self.next_session as *mut mfxSession
+    }
+}
+
+impl MockLoader {
+    fn matches(&self) -> bool {
+        self.filters.iter().all(|(_, name, value)| match &name[..] {
+            b"mfxImplDescription.Impl" => *value == MFX_IMPL_TYPE_HARDWARE,
+            b"mfxImplDescription.mfxEncoderDescription.encoder.CodecID" => CODECS.contains(value),
+            b"mfxImplDescription.ApiVersion.Version" => {
+                *value <= (API_VERSION.Major as mfxU32) << 16 | API_VERSION.Minor as mfxU32
+            }
+            _ => false,
+        })
+    }
+}
+
+fn describe_implementation(name: &[u8]) -> mfxImplDescription {
+    let mut description = mfxImplDescription::new();
+    description.Version = mfxStructVersion::new(1, 0);
+    description.Impl = MFX_IMPL_TYPE_HARDWARE;
+    description.ApiVersion = API_VERSION;
+    for (dst, &src) in description.ImplName.iter_mut().zip(name) {
+        *dst = src as mfxChar;
+    }
+    description
+}
+
+impl MockSession {
+    fn can_encode_hevc(&self) -> bool {
+        self.onevpl || self.plugins.contains(&MFX_PLUGINID_HEVCE_HW)
+    }
+
+    fn add_task(&mut self, task: Task, syncp: *mut mfxSyncPoint) {
+        self.next_sync += 1;
+        self.tasks.insert(self.next_sync, task);
+        unsafe { *syncp = self.next_sync as mfxSyncPoint };
+    }
+
+    // Completes the VPP operation writing to `surface`, if any, which then
+    // needs no synchronization.
+    unsafe fn complete_vpp(&mut self, surface: *const mfxFrameSurface1) {
+        let vpp = self.tasks.iter().find_map(|(&sync, task)| match *task {
+            Task::Vpp { output, .. } if ptr::eq(output, surface) => Some(sync),
+            _ => None,
+        });
+        if let Some(Task::Vpp { input, output }) = vpp.and_then(|sync| self.tasks.remove(&sync)) {
+            run_vpp(input, output);
+        }
+    }
+}
+
+impl MockEncode {
+    unsafe fn new(par: &mfxVideoParam) -> MockEncode {
+        let mut par = *par;
+        let ivf_headers = par.u.mfx.CodecId == MFX_CODEC_VP9
+            && find_ext_buffer::<mfxExtVP9Param>(&par, MFX_EXTBUFF_VP9_PARAM)
+                .is_none_or(|p| p.WriteIVFHeaders != MFX_CODINGOPTION_OFF);
+        let mut coding_option = find_ext_buffer(&par, MFX_EXTBUFF_CODING_OPTION)
+            .copied()
+            .unwrap_or_else(mfxExtCodingOption::new);
+        let mut coding_option2 = find_ext_buffer(&par, MFX_EXTBUFF_CODING_OPTION2)
+            .copied()
+            .unwrap_or_else(mfxExtCodingOption2::new);
+        let mut coding_option3 = find_ext_buffer(&par, MFX_EXTBUFF_CODING_OPTION3)
+            .copied()
+            .unwrap_or_else(mfxExtCodingOption3::new);
+        resolve_coding_options(
+            &par.u.mfx,
+            &mut coding_option,
+            &mut coding_option2,
+            &mut coding_option3,
+        );
+        // only referenced for the duration of the call
+        par.ExtParam = ptr::null();
+        par.NumExtParam = 0;
+        let info = par.u.mfx.FrameInfo;
+        // JPEG has no BufferSizeInKB
+        if par.u.mfx.CodecId != MFX_CODEC_JPEG && par.u.mfx.BufferSizeInKB == 0 {
+            let frame_size = info.Width as usize * info.Height as usize * 3 / 2;
+            let multiplier = par.u.mfx.BRCParamMultiplier.max(1) as usize;
+            par.u.mfx.BufferSizeInKB = (frame_size / 1000 / multiplier + 1) as mfxU16;
+        }
+        MockEncode {
+            par,
+            coding_option,
+            coding_option2,
+            coding_option3,
+            ivf_headers,
+            max_width: info.Width,
+            max_height: info.Height,
+            queue: VecDeque::new(),
+            frame_order: 0,
+            sequence_start: 0,
+        }
+    }
+
+    // Returns the surfaces held back to the application, which has to
+    // submit them again.
+    unsafe fn drop_queue(&mut self) {
+        for (surface, _) in self.queue.drain(..) {
+            (*surface).Data.Locked -= 1;
+        }
+    }
+}
+
+fn check_encode_param(par: &mfxVideoParam, session: &MockSession) -> mfxStatus {
+    let mfx = unsafe { &par.u.mfx };
+    let fourcc = mfx.FrameInfo.FourCC;
+    // profile and surface format supported in LowPower mode
+    let low_power_only = |profiles: &[(mfxU16, &[mfxU32])]| {
+        let profile = mfx.CodecProfile;
+        let supported = profiles.iter().any(|&(p, formats)| {
+            formats.contains(&fourcc) && (profile == MFX_PROFILE_UNKNOWN || profile == p)
+        });
+        supported && mfx.LowPower == MFX_CODINGOPTION_ON
+    };
+    let max_size = match mfx.CodecId {
+        MFX_CODEC_AVC => {
+            if fourcc != MFX_FOURCC_NV12 || !AVC_PROFILES.contains(&mfx.CodecProfile) {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            if mfx.LowPower == MFX_CODINGOPTION_ON {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            AVC_MAX_SIZE
+        }
+        MFX_CODEC_HEVC if session.can_encode_hevc() => {
+            if !low_power_only(HEVC_PROFILES) {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            HEVC_MAX_SIZE
+        }
+        MFX_CODEC_VP9 => {
+            if !low_power_only(VP9_PROFILES) {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            VP9_MAX_SIZE
+        }
+        MFX_CODEC_JPEG => {
+            let jpeg = unsafe { &par.u.jpeg };
+            let profile = mfx.CodecProfile;
+            if profile != MFX_PROFILE_UNKNOWN && profile != MFX_PROFILE_JPEG_BASELINE {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            if !JPEG_FOURCCS.contains(&fourcc) || mfx.LowPower == MFX_CODINGOPTION_ON {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            if jpeg.Quality == 0 || jpeg.Quality > 100 {
+                return MFX_ERR_INVALID_VIDEO_PARAM;
+            }
+            JPEG_MAX_SIZE
+        }
+        MFX_CODEC_AV1 if session.onevpl => {
+            if !low_power_only(AV1_PROFILES) {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            AV1_MAX_SIZE
+        }
+        _ => return MFX_ERR_UNSUPPORTED,
+    };
+    let sts = check_rate_control(mfx);
+    if sts != MFX_ERR_NONE {
+        return sts;
+    }
+    if mfx.FrameInfo.Width > max_size || mfx.FrameInfo.Height > max_size {
+        return MFX_ERR_UNSUPPORTED;
+    }
+    let sts = check_frame_info(&mfx.FrameInfo);
+    if sts != MFX_ERR_NONE {
+        return sts;
+    }
+    // the caller keeps its extension buffers valid for the duration of the call
+    unsafe { check_ext_buffers(par) }
+}
+
+fn check_rate_control(mfx: &mfxInfoMFX) -> mfxStatus {
+    let method = mfx.RateControlMethod;
+    let (methods, max_qp) = match mfx.CodecId {
+        MFX_CODEC_JPEG => return MFX_ERR_NONE,
+        MFX_CODEC_AVC => (RATE_CONTROL_METHODS, MAX_QP),
+        MFX_CODEC_HEVC => (LOW_POWER_RATE_CONTROL_METHODS, MAX_QP),
+        _ => (LOW_POWER_RATE_CONTROL_METHODS, MAX_QINDEX),
+    };
+    if method != 0 && !methods.contains(&method) {
+        return MFX_ERR_UNSUPPORTED;
+    }
+    let valid = |value: mfxU16, max| value != 0 && value <= max;
+    let ok = unsafe {
+        match method {
+            MFX_RATECONTROL_CQP => {
+                valid(mfx.u1.QPI, max_qp) && valid(mfx.u2.QPP, max_qp) && valid(mfx.u3.QPB, max_qp)
+            }
+            MFX_RATECONTROL_ICQ | MFX_RATECONTROL_LA_ICQ => valid(mfx.u2.ICQQuality, MAX_QP),
+            _ => true,
+        }
+    };
+    if ok {
+        MFX_ERR_NONE
+    } else {
+        MFX_ERR_INVALID_VIDEO_PARAM
+    }
+}
+
+unsafe fn check_ext_buffers(par: &mfxVideoParam) -> mfxStatus {
+    if par.NumExtParam == 0 {
+        return MFX_ERR_NONE;
+    }
+    if par.ExtParam.is_null() {
+        return MFX_ERR_NULL_PTR;
+    }
+    let mfx = &par.u.mfx;
+    for &buffer in slice::from_raw_parts(par.ExtParam, par.NumExtParam as usize) {
+        if buffer.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        let avc = mfx.CodecId == MFX_CODEC_AVC;
+        let hevc = mfx.CodecId == MFX_CODEC_HEVC;
+        match (*buffer).BufferId {
+            MFX_EXTBUFF_CODING_OPTION if avc || hevc => {
+                // CAVLC is H.264 only
+                if hevc && (*(buffer as *const mfxExtCodingOption)).CAVLC == MFX_CODINGOPTION_ON {
+                    return MFX_ERR_UNSUPPORTED;
+                }
+            }
+            MFX_EXTBUFF_CODING_OPTION2 if avc || hevc => {
+                // no look-ahead on the LowPower encoders
+                let depth = (*(buffer as *const mfxExtCodingOption2)).LookAheadDepth;
+                if depth != 0 && (hevc || !LOOK_AHEAD_DEPTHS.contains(&depth)) {
+                    return MFX_ERR_INVALID_VIDEO_PARAM;
+                }
+            }
+            MFX_EXTBUFF_CODING_OPTION3 if avc || hevc => {
+                let option = &*(buffer as *const mfxExtCodingOption3);
+                if option.QVBRQuality > MAX_QP {
+                    return MFX_ERR_INVALID_VIDEO_PARAM;
+                }
+                if avc && option.GPB != MFX_CODINGOPTION_UNKNOWN {
+                    return MFX_ERR_UNSUPPORTED;
+                }
+            }
+            // only acted on by a reset
+            MFX_EXTBUFF_ENCODER_RESET_OPTION => {}
+            MFX_EXTBUFF_AV1_TILE_PARAM if mfx.CodecId == MFX_CODEC_AV1 => {
+                let tiles = &*(buffer as *const mfxExtAV1TileParam);
+                let info = &mfx.FrameInfo;
+                let columns = info.Width.div_ceil(AV1_SUPERBLOCK_SIZE);
+                let rows = info.Height.div_ceil(AV1_SUPERBLOCK_SIZE);
+                if tiles.NumTileColumns > columns || tiles.NumTileRows > rows {
+                    return MFX_ERR_UNSUPPORTED;
+                }
+            }
+            MFX_EXTBUFF_VP9_PARAM if mfx.CodecId == MFX_CODEC_VP9 => {
+                let param = &*(buffer as *const mfxExtVP9Param);
+                let columns = (mfx.FrameInfo.Width / VP9_MIN_TILE_WIDTH).max(1);
+                let valid = |n: mfxU16, max| n <= max && (n == 0 || n.is_power_of_two());
+                if !valid(param.NumTileColumns, columns)
+                    || !valid(param.NumTileRows, VP9_MAX_TILE_ROWS)
+                {
+                    return MFX_ERR_UNSUPPORTED;
+                }
+            }
+            MFX_EXTBUFF_VP9_SEGMENTATION if mfx.CodecId == MFX_CODEC_VP9 => {
+                let sts =
+                    check_segmentation(&mfx.FrameInfo, &*(buffer as *const mfxExtVP9Segmentation));
+                if sts != MFX_ERR_NONE {
+                    return sts;
+                }
+            }
+            _ => return MFX_ERR_UNSUPPORTED,
+        }
+    }
+    MFX_ERR_NONE
+}
+
+unsafe fn check_segmentation(info: &mfxFrameInfo, seg: &mfxExtVP9Segmentation) -> mfxStatus {
+    if seg.NumSegments == 0 || seg.NumSegments > VP9_MAX_SEGMENTS {
+        return MFX_ERR_UNSUPPORTED;
+    }
+    let size = seg.SegmentIdBlockSize;
+    if ![8, 16, 32, 64].contains(&size) {
+        return MFX_ERR_UNSUPPORTED;
+    }
+    // the map covers the aligned frame, not the crop
+    let blocks = info.Width.div_ceil(size) as usize * info.Height.div_ceil(size) as usize;
+    if (seg.NumSegmentIdAlloc as usize) < blocks {
+        return MFX_ERR_INVALID_VIDEO_PARAM;
+    }
+    if seg.SegmentId.is_null() {
+        return MFX_ERR_NULL_PTR;
+    }
+    let ids = slice::from_raw_parts(seg.SegmentId, blocks);
+    if ids.iter().any(|&id| id as mfxU16 >= seg.NumSegments) {
+        return MFX_ERR_INVALID_VIDEO_PARAM;
+    }
+    MFX_ERR_NONE
+}
+
+// Settles the options left to the runtime: off, CABAC for H.264 and GPB for
+// HEVC.
+fn resolve_coding_options(
+    mfx: &mfxInfoMFX,
+    co: &mut mfxExtCodingOption,
+    co2: &mut mfxExtCodingOption2,
+    co3: &mut mfxExtCodingOption3,
+) {
+    let resolve = |value: &mut mfxU16, default| {
+        if *value == MFX_CODINGOPTION_UNKNOWN {
+            *value = default;
+        }
+    };
+    let off = MFX_CODINGOPTION_OFF;
+    if mfx.CodecId == MFX_CODEC_AVC {
+        resolve(&mut co.CAVLC, off);
+    }
+    resolve(&mut co.AUDelimiter, off);
+    resolve(&mut co.PicTimingSEI, off);
+    resolve(&mut co.RecoveryPointSEI, off);
+    resolve(&mut co.NalHrdConformance, MFX_CODINGOPTION_ON);
+    resolve(&mut co.VuiNalHrdParameters, MFX_CODINGOPTION_ON);
+    resolve(&mut co2.MBBRC, off);
+    resolve(&mut co2.Trellis, MFX_TRELLIS_OFF);
+    resolve(&mut co2.AdaptiveI, off);
+    resolve(&mut co2.AdaptiveB, off);
+    resolve(&mut co2.BRefType, MFX_B_REF_OFF);
+    resolve(&mut co2.RepeatPPS, off);
+    resolve(&mut co3.WeightedPred, MFX_WEIGHTED_PRED_DEFAULT);
+    resolve(&mut co3.WeightedBiPred, MFX_WEIGHTED_PRED_DEFAULT);
+    resolve(&mut co3.FadeDetection, off);
+    resolve(&mut co3.LowDelayBRC, off);
+    if mfx.CodecId == MFX_CODEC_HEVC {
+        resolve(&mut co3.GPB, MFX_CODINGOPTION_ON);
+    }
+    resolve(&mut co3.TimingInfoPresent, MFX_CODINGOPTION_ON);
+}
+
+unsafe fn find_ext_buffer<T>(par: &mfxVideoParam, id: mfxU32) -> Option<&T> {
+    if par.ExtParam.is_null() {
+        return None;
+    }
+    slice::from_raw_parts(par.ExtParam, par.NumExtParam as usize)
+        .iter()
+        .find(|&&buffer| !buffer.is_null() && (*buffer).BufferId == id)
+        .map(|&buffer| &*(buffer as *const T))
+}
+
+// Wraps a VP9 frame the way the Media SDK does by default.
+fn add_ivf_headers(frame: Vec<u8>, par: &mfxVideoParam, frame_order: u32) -> Vec<u8> {
+    let info = unsafe { &par.u.mfx.FrameInfo };
+    let mut out = Vec::with_capacity(frame.len() + 44);
+    if frame_order == 0 {
+        out.extend_from_slice(b"DKIF");
+        out.extend_from_slice(&[0, 0, 32, 0]);
+        out.extend_from_slice(b"VP90");
+        out.extend_from_slice(&info.CropW.to_le_bytes());
+        out.extend_from_slice(&info.CropH.to_le_bytes());
+        out.extend_from_slice(&info.FrameRateExtN.to_le_bytes());
+        out.extend_from_slice(&info.FrameRateExtD.to_le_bytes());
+        out.extend_from_slice(&[0; 8]);
+    }
+    out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(frame_order as u64).to_le_bytes());
+    out.extend_from_slice(&frame);
+    out
+}
+
+fn check_frame_info(info: &mfxFrameInfo) -> mfxStatus {
+    if info.Width == 0 || !info.Width.is_multiple_of(16) {
+        return MFX_ERR_INVALID_VIDEO_PARAM;
+    }
+    if info.Height == 0 || !info.Height.is_multiple_of(16) {
+        return MFX_ERR_INVALID_VIDEO_PARAM;
+    }
+    if info.CropW > info.Width || info.CropH > info.Height {
+        return MFX_ERR_INVALID_VIDEO_PARAM;
+    }
+    MFX_ERR_NONE
+}
+
+// Inserts emulation prevention bytes so the payload never contains a start code.
+fn push_nal(au: &mut Vec<u8>, header: &[u8], payload: &[u8]) {
+    au.extend_from_slice(&START_CODE);
+    au.extend_from_slice(header);
+    let mut zeros = 0;
+    for &b in payload {
+        if zeros >= 2 && b <= 3 {
+            au.push(3);
+            zeros = 0;
+        }
+        au.push(b);
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+    }
+}
+
+// Writes an OBU with obu_has_size_field set and a LEB128 size.
+fn push_obu(tu: &mut Vec<u8>, obu_type: u8, payload: &[u8]) {
+    tu.push(obu_type << 3 | 0x02);
+    let mut size = payload.len();
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            tu.push(byte);
+            break;
+        }
+        tu.push(byte | 0x80);
+    }
+    tu.extend_from_slice(payload);
+}
+
+fn push_segment(image: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    image.extend_from_slice(&[0xff, marker]);
+    image.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    image.extend_from_slice(body);
+}
+
+fn build_jpeg(par: &mfxVideoParam, payload: &[u8]) -> Vec<u8> {
+    let jpeg = unsafe { &par.u.jpeg };
+    let info = &jpeg.FrameInfo;
+    let mut image = vec![0xff, 0xd8];
+    push_segment(&mut image, 0xdb, &[jpeg.Quality as u8]);
+    let mut frame = vec![8];
+    frame.extend_from_slice(&info.CropH.to_be_bytes());
+    frame.extend_from_slice(&info.CropW.to_be_bytes());
+    // Y sampled 2x2, Cb and Cr 1x1
+    frame.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    push_segment(&mut image, 0xc0, &frame);
+    push_segment(&mut image, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+    for &b in payload {
+        image.push(b);
+        // byte stuffing
+        if b == 0xff {
+            image.push(0);
+        }
+    }
+    image.extend_from_slice(&[0xff, 0xd9]);
+    image
+}
+
+fn pitch(data: &mfxFrameData) -> usize {
+    (data.PitchHigh as usize) << 16 | data.PitchLow as usize
+}
+
+// Sums the 8 most significant bits of every luma sample.
+unsafe fn luma_checksum(surface: &mfxFrameSurface1) -> u32 {
+    let w = surface.Info.CropW as usize;
+    let h = surface.Info.CropH as usize;
+    let pitch = pitch(&surface.Data);
+    // bytes per sample, offset of the most significant byte
+    let (step, msb) = match surface.Info.FourCC {
+        MFX_FOURCC_P010 => (2, 1),
+        MFX_FOURCC_AYUV => (4, 0),
+        _ => (1, 0),
+    };
+    let mut sum: u32 = 0;
+    for row in 0..h {
+        for col in 0..w {
+            let sample = *surface.Data.Y.add(row * pitch + col * step + msb);
+            sum = sum.wrapping_add(sample as u32);
+        }
+    }
+    sum
+}
+
+unsafe fn build_access_unit(
+    encode: &MockEncode,
+    surface: &mfxFrameSurface1,
+    ctrl: &mfxEncodeCtrl,
+) -> (Vec<u8>, mfxU16) {
+    let par = &encode.par;
+    let frame_order = encode.frame_order;
+    let mfx = &par.u.mfx;
+    let info = &mfx.FrameInfo;
+    let mut payload = Vec::with_capacity(8);
+    payload.extend_from_slice(&frame_order.to_be_bytes());
+    payload.extend_from_slice(&luma_checksum(surface).to_be_bytes());
+
+    if mfx.CodecId == MFX_CODEC_JPEG {
+        return (build_jpeg(par, &payload), MFX_FRAMETYPE_I);
+    }
+
+    let gop_frame_type = gop_frame_type(mfx, frame_order - encode.sequence_start);
+    let frame_type = controlled_frame_type(mfx, gop_frame_type, ctrl);
+    let mut au = Vec::new();
+    // I-frames are never skipped
+    let skip = match encode.coding_option2.SkipFrame {
+        _ if ctrl.SkipFrame == 0 || frame_type & MFX_FRAMETYPE_I != 0 => MFX_SKIPFRAME_NO_SKIP,
+        mode => mode,
+    };
+    match skip {
+        MFX_SKIPFRAME_INSERT_NOTHING => return (au, MFX_FRAMETYPE_P),
+        // repeats the reference, with nothing of the surface
+        MFX_SKIPFRAME_INSERT_DUMMY => payload.truncate(4),
+        _ => {}
+    }
+    let aud = encode.coding_option.AUDelimiter == MFX_CODINGOPTION_ON;
+    match mfx.CodecId {
+        // temporal delimiter
+        MFX_CODEC_AV1 => push_obu(&mut au, 2, &[]),
+        // access unit delimiter, any slice type
+        MFX_CODEC_AVC if aud => push_nal(&mut au, &[0x09], &[0xf0]),
+        MFX_CODEC_HEVC if aud => push_nal(&mut au, &[0x46, 0x01], &[0x50]),
+        _ => {}
+    }
+    if frame_type & MFX_FRAMETYPE_IDR != 0 {
+        let mut sps = match mfx.CodecId {
+            MFX_CODEC_HEVC => vec![mfx.CodecProfile.max(1) as u8, 0x00, 0x5d],
+            MFX_CODEC_VP9 | MFX_CODEC_AV1 => {
+                vec![mfx.CodecProfile.max(1) as u8 - 1, 0x00, 0x08]
+            }
+            // profile_idc, constraint_set1_flag for Constrained Baseline,
+            // level_idc, High 4.0 unless set
+            _ => vec![
+                match mfx.CodecProfile & 0xff {
+                    0 => MFX_PROFILE_AVC_HIGH as u8,
+                    profile => profile as u8,
+                },
+                if mfx.CodecProfile & MFX_PROFILE_AVC_CONSTRAINT_SET1 != 0 {
+                    0x40
+                } else {
+                    0x00
+                },
+                match mfx.CodecLevel {
+                    0 => MFX_LEVEL_AVC_4 as u8,
+                    level => level as u8,
+                },
+            ],
+        };
+        sps.extend_from_slice(&info.CropW.to_be_bytes());
+        sps.extend_from_slice(&info.CropH.to_be_bytes());
+        // VUI timing: num_units_in_tick, time_scale
+        sps.extend_from_slice(&info.FrameRateExtD.to_be_bytes());
+        sps.extend_from_slice(&(2 * info.FrameRateExtN).to_be_bytes());
+        match mfx.CodecId {
+            MFX_CODEC_HEVC => {
+                push_nal(&mut au, &[0x40, 0x01], &[0x0c, 0x01, 0xff, 0xff]);
+                push_nal(&mut au, &[0x42, 0x01], &sps);
+                push_nal(&mut au, &[0x44, 0x01], &[0xc1, 0x72, 0xb4]);
+                push_nal(&mut au, &[0x26, 0x01], &payload);
+            }
+            MFX_CODEC_AV1 => {
+                push_obu(&mut au, 1, &sps);
+                push_obu(&mut au, 6, &payload);
+            }
+            MFX_CODEC_VP9 => {
+                // frame marker and key frame, sync code
+                au.extend_from_slice(&[0x82, 0x49, 0x83, 0x42]);
+                au.extend_from_slice(&sps);
+                au.extend_from_slice(&payload);
+            }
+            _ => {
+                push_nal(&mut au, &[0x67], &sps);
+                push_nal(&mut au, &[0x68], &[0xce, 0x3c, 0x80]);
+                push_nal(&mut au, &[0x65], &payload);
+            }
+        }
+    } else {
+        match mfx.CodecId {
+            // CRA
+            MFX_CODEC_HEVC if frame_type & MFX_FRAMETYPE_I != 0 => {
+                push_nal(&mut au, &[0x2a, 0x01], &payload)
+            }
+            MFX_CODEC_HEVC => push_nal(&mut au, &[0x02, 0x01], &payload),
+            MFX_CODEC_AV1 => push_obu(&mut au, 6, &payload),
+            MFX_CODEC_VP9 => {
+                au.push(0x86);
+                au.extend_from_slice(&payload);
+            }
+            _ => push_nal(&mut au, &[0x41], &payload),
+        }
+    }
+    (au, frame_type)
+}
+
+// I-frames every GopPicSize frames, or only the first one if 0. IdrInterval
+// counts the I-frames between IDR frames for H.264, and the I-frames per IDR
+// frame for HEVC, where 0 leaves only the first one. VP9 and AV1 I-frames
+// are key frames.
+fn gop_frame_type(mfx: &mfxInfoMFX, frame_order: u32) -> mfxU16 {
+    const IDR: mfxU16 = MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF | MFX_FRAMETYPE_IDR;
+    let gop = mfx.GopPicSize as u32;
+    if frame_order == 0 {
+        return IDR;
+    }
+    if gop == 0 || !frame_order.is_multiple_of(gop) {
+        return MFX_FRAMETYPE_P | MFX_FRAMETYPE_REF;
+    }
+    let n = frame_order / gop;
+    let idr_interval = mfx.IdrInterval as u32;
+    let idr = match mfx.CodecId {
+        MFX_CODEC_AVC => n.is_multiple_of(idr_interval + 1),
+        MFX_CODEC_HEVC => idr_interval != 0 && n.is_multiple_of(idr_interval),
+        _ => true,
+    };
+    if idr {
+        IDR
+    } else {
+        MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF
+    }
+}
+
+// Applies the frame type an mfxEncodeCtrl forces: an IDR frame, always for
+// VP9 and AV1, or an I-frame, which leaves the GOP cadence alone.
+fn controlled_frame_type(mfx: &mfxInfoMFX, frame_type: mfxU16, ctrl: &mfxEncodeCtrl) -> mfxU16 {
+    const IDR: mfxU16 = MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF | MFX_FRAMETYPE_IDR;
+    let key_frames = mfx.CodecId == MFX_CODEC_VP9 || mfx.CodecId == MFX_CODEC_AV1;
+    if ctrl.FrameType & MFX_FRAMETYPE_I == 0 {
+        frame_type
+    } else if ctrl.FrameType & MFX_FRAMETYPE_IDR != 0 || key_frames {
+        IDR
+    } else {
+        frame_type | MFX_FRAMETYPE_I | MFX_FRAMETYPE_REF
+    }
+}
+
+// Completes a VPP task, unlocking both of its surfaces.
+unsafe fn run_vpp(input: *mut mfxFrameSurface1, output: *mut mfxFrameSurface1) {
+    convert(&*input, &mut *output);
+    (*input).Data.Locked -= 1;
+    (*output).Data.Locked -= 1;
+}
+
+// Converts the YUV 4:2:0 input of VPP, planar or NV12, into its NV12, P010
+// or AYUV output.
+unsafe fn convert(input: &mfxFrameSurface1, output: &mut mfxFrameSurface1) {
+    let w = input.Info.CropW.min(output.Info.CropW) as usize;
+    let h = input.Info.CropH.min(output.Info.CropH) as usize;
+    let pitch_in = pitch(&input.Data);
+    let pitch_out = pitch(&output.Data);
+
+    let luma = |row: usize, col: usize| *input.Data.Y.add(row * pitch_in + col);
+    // chroma of the 2x2 block at (row, col)
+    let chroma = |row: usize, col: usize| {
+        if input.Info.FourCC == MFX_FOURCC_NV12 {
+            let uv = input.Data.UV.add(row * pitch_in + 2 * col);
+            (*uv, *uv.add(1))
+        } else {
+            let offset = row * pitch_in / 2 + col;
+            (*input.Data.UV.add(offset), *input.Data.V.add(offset))
+        }
+    };
+
+    let out = &output.Data;
+    match output.Info.FourCC {
+        MFX_FOURCC_AYUV => {
+            for row in 0..h {
+                for col in 0..w {
+                    let (u, v) = chroma(row / 2, col / 2);
+                    let pixel = out.V.add(row * pitch_out + 4 * col);
+                    *pixel = v;
+                    *pixel.add(1) = u;
+                    *pixel.add(2) = luma(row, col);
+                    *pixel.add(3) = 0xff;
+                }
+            }
+        }
+        MFX_FOURCC_P010 => {
+            // 10-bit samples in the upper bits of little-endian words
+            for row in 0..h {
+                for col in 0..w {
+                    let sample = out.Y.add(row * pitch_out + 2 * col);
+                    *sample = 0;
+                    *sample.add(1) = luma(row, col);
+                }
+            }
+            for row in 0..h / 2 {
+                for col in 0..w / 2 {
+                    let (u, v) = chroma(row, col);
+                    let sample = out.UV.add(row * pitch_out + 4 * col);
+                    *sample = 0;
+                    *sample.add(1) = u;
+                    *sample.add(2) = 0;
+                    *sample.add(3) = v;
+                }
+            }
+        }
+        _ => {
+            for row in 0..h {
+                for col in 0..w {
+                    *out.Y.add(row * pitch_out + col) = luma(row, col);
+                }
+            }
+            for row in 0..h / 2 {
+                for col in 0..w / 2 {
+                    let (u, v) = chroma(row, col);
+                    let sample = out.UV.add(row * pitch_out + 2 * col);
+                    *sample = u;
+                    *sample.add(1) = v;
+                }
+            }
+        }
+    }
+
+    output.Data.TimeStamp = input.Data.TimeStamp;
+    output.Data.FrameOrder = input.Data.FrameOrder;
+}
+
+impl Backend for MockBackend {
+    unsafe fn init(
+        &self,
+        implementation: mfxIMPL,
+        _ver: *const mfxVersion,
+        session: *mut *mut mfxSession,
+    ) -> mfxStatus {
+        if session.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        // a single hardware adapter
+        match implementation & 0xff {
+            MFX_IMPL_AUTO | MFX_IMPL_HARDWARE | MFX_IMPL_AUTO_ANY | MFX_IMPL_HARDWARE_ANY => {}
+            _ => return MFX_ERR_UNSUPPORTED,
+        }
+        *session = self.state.lock().unwrap().new_session(false);
+        MFX_ERR_NONE
+    }
+
+    unsafe fn close(&self, session: *mut mfxSession) -> mfxStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.sessions.remove(&(session as usize)) {
+            Some(_) => MFX_ERR_NONE,
+            None => MFX_ERR_INVALID_HANDLE,
+        }
+    }
+
+    unsafe fn query_impl(
+        &self,
+        session: *const mfxSession,
+        implementation: *mut mfxIMPL,
+    ) -> mfxStatus {
+        self.with_session(session, |_| {
+            *implementation = MFX_IMPL_HARDWARE;
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn query_version(
+        &self,
+        session: *const mfxSession,
+        version: *mut mfxVersion,
+    ) -> mfxStatus {
+        let api_version = if self.dispatcher {
+            API_VERSION
+        } else {
+            LEGACY_API_VERSION
+        };
+        self.with_session(session, |_| {
+            *version = api_version;
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn user_load(
+        &self,
+        session: *const mfxSession,
+        uid: *const mfxPluginUID,
+        _version: mfxU32,
+    ) -> mfxStatus {
+        // oneVPL has no plugins, every encoder is built in
+        if self.dispatcher {
+            return MFX_ERR_UNSUPPORTED;
+        }
+        if uid.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| {
+            if *uid != MFX_PLUGINID_HEVCE_HW {
+                return MFX_ERR_NOT_FOUND;
+            }
+            if s.plugins.contains(&*uid) {
+                return MFX_ERR_UNDEFINED_BEHAVIOR;
+            }
+            s.plugins.push(*uid);
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn user_unload(
+        &self,
+        session: *const mfxSession,
+        uid: *const mfxPluginUID,
+    ) -> mfxStatus {
+        if uid.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| {
+            match s.plugins.iter().position(|p| *p == *uid) {
+                Some(i) => {
+                    s.plugins.remove(i);
+                    MFX_ERR_NONE
+                }
+                None => MFX_ERR_NOT_FOUND,
+            }
+        })
+    }
+
+    unsafe fn sync_operation(
+        &self,
+        session: *const mfxSession,
+        syncp: mfxSyncPoint,
+        _wait: mfxU32,
+    ) -> mfxStatus {
+        if syncp.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| match s.tasks.remove(&(syncp as usize)) {
+            Some(Task::Vpp { input, output }) => {
+                run_vpp(input, output);
+                MFX_ERR_NONE
+            }
+            Some(Task::Encode {
+                surface,
+                bs,
+                au,
+                frame_type,
+            }) => {
+                let bs = &mut *bs;
+                let offset = (bs.DataOffset + bs.DataLength) as usize;
+                let data = bs.Data as *mut mfxU8;
+                ptr::copy_nonoverlapping(au.as_ptr(), data.add(offset), au.len());
+                bs.DataLength += au.len() as mfxU32;
+                bs.TimeStamp = (*surface).Data.TimeStamp;
+                bs.DecodeTimeStamp = bs.TimeStamp as mfxI64;
+                bs.FrameType = frame_type;
+                bs.PicStruct = MFX_PICSTRUCT_PROGRESSIVE;
+                (*surface).Data.Locked -= 1;
+                MFX_ERR_NONE
+            }
+            None => MFX_ERR_NOT_FOUND,
+        })
+    }
+
+    unsafe fn encode_query(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxVideoParam,
+        output: *mut mfxVideoParam,
+    ) -> mfxStatus {
+        if input.is_null() || output.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| {
+            if !ptr::eq(input, output) {
+                *output = *input;
+            }
+            check_encode_param(&*output, s)
+        })
+    }
+
+    unsafe fn encode_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus {
+        self.with_session(session, |s| {
+            let par = &*par;
+            let encode_delay = self.delay(par) as mfxU16;
+            let request = &mut *request;
+            request.Info = par.u.mfx.FrameInfo;
+            request.NumFrameMin = encode_delay + 1;
+            request.NumFrameSuggested = encode_delay + par.AsyncDepth.max(1);
+            check_encode_param(par, s)
+        })
+    }
+
+    unsafe fn encode_init(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus {
+        self.with_session(session, |s| {
+            if s.encode.is_some() {
+                return MFX_ERR_UNDEFINED_BEHAVIOR;
+            }
+            let par = &*par;
+            let sts = check_encode_param(par, s);
+            if sts != MFX_ERR_NONE {
+                return sts;
+            }
+            s.encode = Some(MockEncode::new(par));
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn encode_get_video_param(
+        &self,
+        session: *const mfxSession,
+        par: *mut mfxVideoParam,
+    ) -> mfxStatus {
+        self.with_session(session, |s| match s.encode {
+            Some(ref encode) => {
+                let par = &mut *par;
+                par.AsyncDepth = encode.par.AsyncDepth;
+                par.IOPattern = encode.par.IOPattern;
+                par.u.mfx = encode.par.u.mfx;
+                if par.NumExtParam != 0 && par.ExtParam.is_null() {
+                    return MFX_ERR_NULL_PTR;
+                }
+                for i in 0..par.NumExtParam as usize {
+                    let buffer = *par.ExtParam.add(i) as *mut mfxExtBuffer;
+                    if buffer.is_null() {
+                        return MFX_ERR_NULL_PTR;
+                    }
+                    match (*buffer).BufferId {
+                        MFX_EXTBUFF_CODING_OPTION => {
+                            *(buffer as *mut mfxExtCodingOption) = encode.coding_option
+                        }
+                        MFX_EXTBUFF_CODING_OPTION2 => {
+                            *(buffer as *mut mfxExtCodingOption2) = encode.coding_option2
+                        }
+                        MFX_EXTBUFF_CODING_OPTION3 => {
+                            *(buffer as *mut mfxExtCodingOption3) = encode.coding_option3
+                        }
+                        _ => return MFX_ERR_UNSUPPORTED,
+                    }
+                }
+                MFX_ERR_NONE
+            }
+            None => MFX_ERR_NOT_INITIALIZED,
+        })
+    }
+
+    unsafe fn encode_frame_async(
+        &self,
+        session: *const mfxSession,
+        ctrl: *const mfxEncodeCtrl,
+        surface: *const mfxFrameSurface1,
+        bs: *mut mfxBitstream,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus {
+        if bs.is_null() || syncp.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| {
+            // the runtime writes to the bitstream of a task until it is synchronized
+            let bs_in_flight = s.tasks.values().any(|task| match *task {
+                Task::Encode { bs: task_bs, .. } => ptr::eq(task_bs, bs),
+                Task::Vpp { .. } => false,
+            });
+            if bs_in_flight {
+                return MFX_ERR_UNDEFINED_BEHAVIOR;
+            }
+            // ENCODE waits for the VPP output it is given
+            if !surface.is_null() {
+                s.complete_vpp(surface);
+                if s.vpp_outputs.contains(&(surface as usize)) {
+                    s.encoded_vpp_outputs += 1;
+                }
+            }
+            let encode = match s.encode {
+                Some(ref mut encode) => encode,
+                None => return MFX_ERR_NOT_INITIALIZED,
+            };
+            let encode_delay = self.delay(&encode.par);
+
+            if !surface.is_null() {
+                let ctrl = if ctrl.is_null() {
+                    mfxEncodeCtrl::new()
+                } else {
+                    *ctrl
+                };
+                let mfx = &encode.par.u.mfx;
+                let max_qp = match mfx.CodecId {
+                    MFX_CODEC_VP9 | MFX_CODEC_AV1 => MAX_QINDEX,
+                    _ => MAX_QP,
+                };
+                if mfx.RateControlMethod == MFX_RATECONTROL_CQP && ctrl.QP > max_qp {
+                    return MFX_ERR_INVALID_VIDEO_PARAM;
+                }
+                let surface = surface as *mut mfxFrameSurface1;
+                (*surface).Data.Locked += 1;
+                encode.queue.push_back((surface, ctrl));
+                if encode.queue.len() <= encode_delay {
+                    return MFX_ERR_MORE_DATA;
+                }
+            }
+            let (surface, ctrl) = match encode.queue.pop_front() {
+                Some(queued) => queued,
+                None => return MFX_ERR_MORE_DATA,
+            };
+
+            let (mut au, frame_type) = build_access_unit(encode, &*surface, &ctrl);
+            if encode.ivf_headers {
+                au = add_ivf_headers(au, &encode.par, encode.frame_order);
+            }
+            let b = &*bs;
+            if ((b.MaxLength - b.DataOffset - b.DataLength) as usize) < au.len() {
+                encode.queue.push_front((surface, ctrl));
+                return MFX_ERR_NOT_ENOUGH_BUFFER;
+            }
+            encode.frame_order += 1;
+
+            let task = Task::Encode {
+                surface,
+                bs,
+                au,
+                frame_type,
+            };
+            s.add_task(task, syncp);
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn encode_reset(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+    ) -> mfxStatus {
+        self.with_session(session, |s| {
+            let par = &*par;
+            let sts = check_encode_param(par, s);
+            let encode = match s.encode {
+                Some(ref mut encode) => encode,
+                None => return MFX_ERR_NOT_INITIALIZED,
+            };
+            if sts != MFX_ERR_NONE {
+                return sts;
+            }
+            if self.failing_encode_reset {
+                return MFX_ERR_DEVICE_FAILED;
+            }
+            let (old, new) = (&encode.par.u.mfx, &par.u.mfx);
+            if new.CodecId != old.CodecId {
+                return MFX_ERR_INVALID_VIDEO_PARAM;
+            }
+            // what the running encoder cannot change: its surfaces, profile,
+            // engine, rate control method and GOP pattern
+            if new.FrameInfo.Width > encode.max_width
+                || new.FrameInfo.Height > encode.max_height
+                || new.FrameInfo.FourCC != old.FrameInfo.FourCC
+                || new.CodecProfile != old.CodecProfile
+                || new.LowPower != old.LowPower
+                || new.RateControlMethod != old.RateControlMethod
+                || new.GopRefDist != old.GopRefDist
+            {
+                return MFX_ERR_INCOMPATIBLE_VIDEO_PARAM;
+            }
+            // a new frame size starts a new sequence anyway
+            let new_sequence = new.FrameInfo.CropW != old.FrameInfo.CropW
+                || new.FrameInfo.CropH != old.FrameInfo.CropH
+                || find_ext_buffer::<mfxExtEncoderResetOption>(
+                    par,
+                    MFX_EXTBUFF_ENCODER_RESET_OPTION,
+                )
+                .is_some_and(|option| option.StartNewSequence == MFX_CODINGOPTION_ON);
+
+            encode.drop_queue();
+            let mut reset = MockEncode::new(par);
+            reset.ivf_headers = encode.ivf_headers;
+            reset.max_width = encode.max_width;
+            reset.max_height = encode.max_height;
+            reset.frame_order = encode.frame_order;
+            reset.sequence_start = if new_sequence {
+                encode.frame_order
+            } else {
+                encode.sequence_start
+            };
+            *encode = reset;
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn encode_close(&self, session: *const mfxSession) -> mfxStatus {
+        self.with_session(session, |s| match s.encode.take() {
+            Some(mut encode) => {
+                encode.drop_queue();
+                MFX_ERR_NONE
+            }
+            None => MFX_ERR_NOT_INITIALIZED,
+        })
+    }
+
+    unsafe fn vpp_query_io_surf(
+        &self,
+        session: *const mfxSession,
+        par: *const mfxVideoParam,
+        request: *mut mfxFrameAllocRequest,
+    ) -> mfxStatus {
+        self.with_session(session, |_| {
+            let par = &*par;
+            let request = slice::from_raw_parts_mut(request, 2);
+            request[0].Info = par.u.vpp.In;
+            request[1].Info = par.u.vpp.Out;
+            for r in request.iter_mut() {
+                r.NumFrameMin = 1;
+                r.NumFrameSuggested = par.AsyncDepth.max(1);
+            }
+            check_frame_info(&par.u.vpp.In)
+        })
+    }
+
+    unsafe fn vpp_init(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus {
+        self.with_session(session, |s| {
+            if s.vpp.is_some() {
+                return MFX_ERR_UNDEFINED_BEHAVIOR;
+            }
+            let par = *par;
+            match par.u.vpp.Out.FourCC {
+                MFX_FOURCC_NV12 | MFX_FOURCC_P010 | MFX_FOURCC_AYUV => {}
+                _ => return MFX_ERR_UNSUPPORTED,
+            }
+            let sts = check_frame_info(&par.u.vpp.In);
+            if sts != MFX_ERR_NONE {
+                return sts;
+            }
+            s.vpp = Some(par);
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn vpp_reset(&self, session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus {
+        self.with_session(session, |s| {
+            let vpp = match s.vpp {
+                Some(ref mut vpp) => vpp,
+                None => return MFX_ERR_NOT_INITIALIZED,
+            };
+            let par = *par;
+            let (old, new) = (&vpp.u.vpp, &par.u.vpp);
+            if new.In.FourCC != old.In.FourCC || new.Out.FourCC != old.Out.FourCC {
+                return MFX_ERR_INCOMPATIBLE_VIDEO_PARAM;
+            }
+            let sts = check_frame_info(&new.In);
+            if sts != MFX_ERR_NONE {
+                return sts;
+            }
+            *vpp = par;
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn vpp_run_frame_async(
+        &self,
+        session: *const mfxSession,
+        input: *const mfxFrameSurface1,
+        output: *mut mfxFrameSurface1,
+        _aux: *const mfxExtVppAuxData,
+        syncp: *mut mfxSyncPoint,
+    ) -> mfxStatus {
+        if output.is_null() || syncp.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        self.with_session(session, |s| {
+            if s.vpp.is_none() {
+                return MFX_ERR_NOT_INITIALIZED;
+            }
+            // nothing is buffered, so draining has no output
+            if input.is_null() {
+                return MFX_ERR_MORE_DATA;
+            }
+            if (*output).Data.Locked != 0 {
+                return MFX_ERR_UNDEFINED_BEHAVIOR;
+            }
+
+            let input = input as *mut mfxFrameSurface1;
+            (*input).Data.Locked += 1;
+            (*output).Data.Locked += 1;
+            s.vpp_outputs.insert(output as usize);
+            s.add_task(Task::Vpp { input, output }, syncp);
+            MFX_ERR_NONE
+        })
+    }
+
+    unsafe fn vpp_close(&self, session: *const mfxSession) -> mfxStatus {
+        self.with_session(session, |s| match s.vpp.take() {
+            Some(_) => MFX_ERR_NONE,
+            None => MFX_ERR_NOT_INITIALIZED,
+        })
+    }
+
+    unsafe fn load(&self) -> mfxLoader {
+        if !self.dispatcher {
+            return ptr::null_mut();
+        }
+        let mut state = self.state.lock().unwrap();
+        state.next_handle += 1;
+        let handle = state.next_handle;
+        state.loaders.insert(handle, MockLoader::default());
+        handle as mfxLoader
+    }
+
+    unsafe fn unload(&self, loader: mfxLoader) {
+        let mut state = self.state.lock().unwrap();
+        if state.loaders.remove(&(loader as usize)).is_some() {
+            state.configs.retain(|_, l| *l != loader as usize);
+        }
+    }
+
+    unsafe fn create_config(&self, loader: mfxLoader) -> mfxConfig {
+        let mut state = self.state.lock().unwrap();
+        if !state.loaders.contains_key(&(loader as usize)) {
+            return ptr::null_mut();
+        }
+        state.next_handle += 1;
+        let handle = state.next_handle;
+        state.configs.insert(handle, loader as usize);
+        handle as mfxConfig
+    }
+
+    unsafe fn set_config_filter_property(
+        &self,
+        config: mfxConfig,
+        name: *const mfxU8,
+        value: mfxVariant,
+    ) -> mfxStatus {
+        if name.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        if value.Type != MFX_VARIANT_TYPE_U32 {
+            return MFX_ERR_UNSUPPORTED;
+        }
+        let name = CStr::from_ptr(name as *const libc::c_char)
+            .to_bytes()
+            .to_vec();
+        let mut state = self.state.lock().unwrap();
+        let loader = match state.configs.get(&(config as usize)) {
+            Some(&loader) => loader,
+            None => return MFX_ERR_INVALID_HANDLE,
+        };
+        let filters = &mut state.loaders.get_mut(&loader).unwrap().filters;
+        filters.retain(|(c, n, _)| *c != config as usize || *n != name);
+        filters.push((config as usize, name, value.Data.U32));
+        MFX_ERR_NONE
+    }
+
+    unsafe fn enum_implementations(
+        &self,
+        loader: mfxLoader,
+        i: mfxU32,
+        format: mfxImplCapsDeliveryFormat,
+        idesc: *mut mfxHDL,
+    ) -> mfxStatus {
+        if idesc.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        if format != MFX_IMPLCAPS_IMPLDESCSTRUCTURE {
+            return MFX_ERR_UNSUPPORTED;
+        }
+        let mut state = self.state.lock().unwrap();
+        let loader = match state.loaders.get_mut(&(loader as usize)) {
+            Some(loader) => loader,
+            None => return MFX_ERR_INVALID_HANDLE,
+        };
+        let description = match i {
+            0 => describe_implementation(b"mock"),
+            1 if self.broken_implementation => describe_implementation(b"broken"),
+            _ => return MFX_ERR_NOT_FOUND,
+        };
+        if !loader.matches() {
+            return MFX_ERR_NOT_FOUND;
+        }
+        loader.descriptions += 1;
+        *idesc = Box::into_raw(Box::new(description)) as mfxHDL;
+        MFX_ERR_NONE
+    }
+
+    unsafe fn create_session(
+        &self,
+        loader: mfxLoader,
+        i: mfxU32,
+        session: *mut *mut mfxSession,
+    ) -> mfxStatus {
+        if session.is_null() {
+            return MFX_ERR_NULL_PTR;
+        }
+        let mut state = self.state.lock().unwrap();
+        match state.loaders.get(&(loader as usize)) {
+            Some(loader) if i == 0 && loader.matches() => {}
+            Some(loader) if i == 1 && self.broken_implementation && loader.matches() => {
+                return MFX_ERR_UNSUPPORTED;
+            }
+            Some(_) => return MFX_ERR_NOT_FOUND,
+            None => return MFX_ERR_INVALID_HANDLE,
+        }
+        *session = state.new_session(true);
+        MFX_ERR_NONE
+    }
+
+    unsafe fn release_impl_description(&self, loader: mfxLoader, hdl: mfxHDL) -> mfxStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.loaders.get_mut(&(loader as usize)) {
+            Some(loader) if loader.descriptions > 0 && !hdl.is_null() => {
+                loader.descriptions -= 1;
+                drop(Box::from_raw(hdl as *mut mfxImplDescription));
+                MFX_ERR_NONE
+            }
+            Some(_) => MFX_ERR_NULL_PTR,
+            None => MFX_ERR_INVALID_HANDLE,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::sys::*;
+
+/// `mfxExtCodingOption` settings, H.264 and HEVC only. `None` leaves an
+/// option to the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodingOption {
+    /// CAVLC entropy coding instead of CABAC. H.264 only, and the only one
+    /// the Baseline profiles allow.
+    pub cavlc: Option<bool>,
+    /// Starts every access unit with an access unit delimiter.
+    pub aud: Option<bool>,
+    /// Writes a picture timing SEI with every picture.
+    pub pic_timing_sei: Option<bool>,
+    /// Writes a recovery point SEI with every I-frame that is not an IDR
+    /// frame.
+    pub recovery_point_sei: Option<bool>,
+    /// Keeps the stream conformant to the HRD buffer model.
+    pub nal_hrd_conformance: Option<bool>,
+    /// Writes the NAL HRD parameters to the VUI.
+    pub vui_nal_hrd_parameters: Option<bool>,
+    /// Frames a decoder has to buffer, written to the VUI.
+    pub max_dec_frame_buffering: Option<u16>,
+}
+
+/// `mfxExtCodingOption2` settings, H.264 and HEVC only. `None` leaves an
+/// option to the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodingOption2 {
+    /// Largest frame in bytes.
+    pub max_frame_size: Option<u32>,
+    /// Largest slice in bytes, adding slices as needed.
+    pub max_slice_size: Option<u32>,
+    /// Macroblock level rate control, varying the quantizer within a frame.
+    pub mbbrc: Option<bool>,
+    /// Frame types using trellis quantization.
+    pub trellis: Option<Trellis>,
+    /// Inserts I-frames on scene changes.
+    pub adaptive_i: Option<bool>,
+    /// Adapts the number of B-frames to the content.
+    pub adaptive_b: Option<bool>,
+    /// Lets B-frames refer to other B-frames in a pyramid.
+    pub b_pyramid: Option<bool>,
+    /// Repeats the PPS with every frame.
+    pub repeat_pps: Option<bool>,
+    /// What frames skipped through [`FrameControl::skip`] turn into.
+    ///
+    /// [`FrameControl::skip`]: crate::FrameControl::skip
+    pub skip_frame: Option<SkipFrame>,
+}
+
+/// `mfxExtCodingOption3` settings, H.264 and HEVC only. `None` leaves an
+/// option to the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodingOption3 {
+    /// Weighted prediction of P-frames, default or explicit.
+    pub weighted_pred: Option<WeightedPrediction>,
+    /// Weighted prediction of B-frames.
+    pub weighted_bipred: Option<WeightedPrediction>,
+    /// Detects fades, for the weighted prediction tables.
+    pub fade_detection: Option<bool>,
+    /// Keeps every frame close to its share of the bitrate, for low latency
+    /// streaming.
+    pub low_delay_brc: Option<bool>,
+    /// Codes P-frames as generalized P/B frames. HEVC only.
+    pub gpb: Option<bool>,
+    /// Writes the frame rate to the VUI timing info.
+    pub timing_info_present: Option<bool>,
+    /// Largest I-frame in bytes.
+    pub max_frame_size_i: Option<u32>,
+    /// Largest P-frame in bytes.
+    pub max_frame_size_p: Option<u32>,
+}
+
+/// Frame types using trellis quantization. All false turns it off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Trellis {
+    pub i: bool,
+    pub p: bool,
+    pub b: bool,
+}
+
+/// How the encoder skips a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipFrame {
+    /// A frame repeating its reference.
+    InsertDummy,
+    /// Nothing, leaving an empty packet.
+    InsertNothing,
+    /// A normally coded frame the rate control counts as skipped, for
+    /// applications inserting their own.
+    BrcOnly,
+}
+
+/// Weighted prediction mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightedPrediction {
+    /// Default weights, i.e. none.
+    Default,
+    /// Weights written to the slice headers.
+    Explicit,
+    /// Weights derived from the frame distances. B-frames only.
+    Implicit,
+}
+
+fn tri_state(value: Option<bool>) -> mfxU16 {
+    match value {
+        None => MFX_CODINGOPTION_UNKNOWN,
+        Some(true) => MFX_CODINGOPTION_ON,
+        Some(false) => MFX_CODINGOPTION_OFF,
+    }
+}
+
+fn from_tri_state(value: mfxU16) -> Option<bool> {
+    match value {
+        MFX_CODINGOPTION_ON => Some(true),
+        MFX_CODINGOPTION_OFF => Some(false),
+        _ => None,
+    }
+}
+
+// 0 is "unset" for the sizes
+fn from_size<T: Default + PartialEq>(value: T) -> Option<T> {
+    Some(value).filter(|value| *value != T::default())
+}
+
+impl CodingOption {
+    pub(crate) fn write(&self, ext: &mut mfxExtCodingOption) {
+        ext.CAVLC = tri_state(self.cavlc);
+        ext.AUDelimiter = tri_state(self.aud);
+        ext.PicTimingSEI = tri_state(self.pic_timing_sei);
+        ext.RecoveryPointSEI = tri_state(self.recovery_point_sei);
+        ext.NalHrdConformance = tri_state(self.nal_hrd_conformance);
+        ext.VuiNalHrdParameters = tri_state(self.vui_nal_hrd_parameters);
+        ext.MaxDecFrameBuffering = self.max_dec_frame_buffering.unwrap_or(0);
+    }
+
+    pub(crate) fn read(ext: &mfxExtCodingOption) -> CodingOption {
+        CodingOption {
+            cavlc: from_tri_state(ext.CAVLC),
+            aud: from_tri_state(ext.AUDelimiter),
+            pic_timing_sei: from_tri_state(ext.PicTimingSEI),
+            recovery_point_sei: from_tri_state(ext.RecoveryPointSEI),
+            nal_hrd_conformance: from_tri_state(ext.NalHrdConformance),
+            vui_nal_hrd_parameters: from_tri_state(ext.VuiNalHrdParameters),
+            max_dec_frame_buffering: from_size(ext.MaxDecFrameBuffering),
+        }
+    }
+}
+
+impl CodingOption2 {
+    pub(crate) fn write(&self, ext: &mut mfxExtCodingOption2) {
+        ext.MaxFrameSize = self.max_frame_size.unwrap_or(0);
+        ext.MaxSliceSize = self.max_slice_size.unwrap_or(0);
+        ext.MBBRC = tri_state(self.mbbrc);
+        ext.Trellis = match self.trellis {
+            None => MFX_TRELLIS_UNKNOWN,
+            Some(Trellis {
+                i: false,
+                p: false,
+                b: false,
+            }) => MFX_TRELLIS_OFF,
+            Some(trellis) => {
+                let mut value = 0;
+                if trellis.i {
+                    value |= MFX_TRELLIS_I;
+                }
+                if trellis.p {
+                    value |= MFX_TRELLIS_P;
+                }
+                if trellis.b {
+                    value |= MFX_TRELLIS_B;
+                }
+                value
+            }
+        };
+        ext.AdaptiveI = tri_state(self.adaptive_i);
+        ext.AdaptiveB = tri_state(self.adaptive_b);
+        ext.BRefType = match self.b_pyramid {
+            None => MFX_B_REF_UNKNOWN,
+            Some(true) => MFX_B_REF_PYRAMID,
+            Some(false) => MFX_B_REF_OFF,
+        };
+        ext.RepeatPPS = tri_state(self.repeat_pps);
+        ext.SkipFrame = match self.skip_frame {
+            None => MFX_SKIPFRAME_NO_SKIP,
+            Some(SkipFrame::InsertDummy) => MFX_SKIPFRAME_INSERT_DUMMY,
+            Some(SkipFrame::InsertNothing) => MFX_SKIPFRAME_INSERT_NOTHING,
+            Some(SkipFrame::BrcOnly) => MFX_SKIPFRAME_BRC_ONLY,
+        };
+    }
+
+    pub(crate) fn read(ext: &mfxExtCodingOption2) -> CodingOption2 {
+        CodingOption2 {
+            max_frame_size: from_size(ext.MaxFrameSize),
+            max_slice_size: from_size(ext.MaxSliceSize),
+            mbbrc: from_tri_state(ext.MBBRC),
+            trellis: match ext.Trellis {
+                MFX_TRELLIS_UNKNOWN => None,
+                value => Some(Trellis {
+                    i: value & MFX_TRELLIS_I != 0,
+                    p: value & MFX_TRELLIS_P != 0,
+                    b: value & MFX_TRELLIS_B != 0,
+                }),
+            },
+            adaptive_i: from_tri_state(ext.AdaptiveI),
+            adaptive_b: from_tri_state(ext.AdaptiveB),
+            b_pyramid: match ext.BRefType {
+                MFX_B_REF_PYRAMID => Some(true),
+                MFX_B_REF_OFF => Some(false),
+                _ => None,
+            },
+            repeat_pps: from_tri_state(ext.RepeatPPS),
+            skip_frame: match ext.SkipFrame {
+                MFX_SKIPFRAME_INSERT_DUMMY => Some(SkipFrame::InsertDummy),
+                MFX_SKIPFRAME_INSERT_NOTHING => Some(SkipFrame::InsertNothing),
+                MFX_SKIPFRAME_BRC_ONLY => Some(SkipFrame::BrcOnly),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl CodingOption3 {
+    pub(crate) fn write(&self, ext: &mut mfxExtCodingOption3) {
+        ext.WeightedPred = WeightedPrediction::value(self.weighted_pred);
+        ext.WeightedBiPred = WeightedPrediction::value(self.weighted_bipred);
+        ext.FadeDetection = tri_state(self.fade_detection);
+        ext.LowDelayBRC = tri_state(self.low_delay_brc);
+        ext.GPB = tri_state(self.gpb);
+        ext.TimingInfoPresent = tri_state(self.timing_info_present);
+        ext.MaxFrameSizeI = self.max_frame_size_i.unwrap_or(0);
+        ext.MaxFrameSizeP = self.max_frame_size_p.unwrap_or(0);
+    }
+
+    pub(crate) fn read(ext: &mfxExtCodingOption3) -> CodingOption3 {
+        CodingOption3 {
+            weighted_pred: WeightedPrediction::from_value(ext.WeightedPred),
+            weighted_bipred: WeightedPrediction::from_value(ext.WeightedBiPred),
+            fade_detection: from_tri_state(ext.FadeDetection),
+            low_delay_brc: from_tri_state(ext.LowDelayBRC),
+            gpb: from_tri_state(ext.GPB),
+            timing_info_present: from_tri_state(ext.TimingInfoPresent),
+            max_frame_size_i: from_size(ext.MaxFrameSizeI),
+            max_frame_size_p: from_size(ext.MaxFrameSizeP),
+        }
+    }
+}
+
+impl WeightedPrediction {
+    fn value(mode: Option<WeightedPrediction>) -> mfxU16 {
+        match mode {
+            None => MFX_WEIGHTED_PRED_UNKNOWN,
+            Some(WeightedPrediction::Default) => MFX_WEIGHTED_PRED_DEFAULT,
+            Some(WeightedPrediction::Explicit) => MFX_WEIGHTED_PRED_EXPLICIT,
+            Some(WeightedPrediction::Implicit) => MFX_WEIGHTED_PRED_IMPLICIT,
+        }
+    }
+
+    fn from_value(value: mfxU16) -> Option<WeightedPrediction> {
+        match value {
+            MFX_WEIGHTED_PRED_DEFAULT => Some(WeightedPrediction::Default),
+            MFX_WEIGHTED_PRED_EXPLICIT => Some(WeightedPrediction::Explicit),
+            MFX_WEIGHTED_PRED_IMPLICIT => Some(WeightedPrediction::Implicit),
+            _ => None,
+        }
+    }
+}
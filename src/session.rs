@@ -0,0 +1,398 @@
+use std::ffi::CStr;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::backend::Backend;
+use crate::error::{check, Error, MfxError, MfxWarning, Result};
+use crate::sys::*;
+
+const IMPL_FILTER: &[u8] = b"mfxImplDescription.Impl\0";
+const CODEC_FILTER: &[u8] = b"mfxImplDescription.mfxEncoderDescription.encoder.CodecID\0";
+const API_VERSION_FILTER: &[u8] = b"mfxImplDescription.ApiVersion.Version\0";
+
+/// Media SDK session owning the components initialized on it.
+///
+/// Dropping the session closes ENCODE, then VPP, then the session itself, so
+/// an error anywhere in the pipeline setup releases every driver resource
+/// acquired so far.
+pub(crate) struct Session {
+    backend: Arc<dyn Backend>,
+    raw: *mut mfxSession,
+    vpp: Option<VppComponent>,
+    encode: Option<EncodeComponent>,
+    plugins: Vec<PluginComponent>,
+    // unloaded after the session is closed
+    loader: Option<Rc<Loader>>,
+}
+
+/// oneVPL dispatcher loader, unloaded on drop together with its configs.
+pub(crate) struct Loader {
+    backend: Arc<dyn Backend>,
+    raw: mfxLoader,
+}
+
+/// Initialized ENCODE component, closed on drop.
+pub(crate) struct EncodeComponent {
+    backend: Arc<dyn Backend>,
+    session: *mut mfxSession,
+}
+
+/// Initialized VPP component, closed on drop.
+pub(crate) struct VppComponent {
+    backend: Arc<dyn Backend>,
+    session: *mut mfxSession,
+}
+
+/// Loaded Media SDK 1.x plugin, unloaded on drop.
+pub(crate) struct PluginComponent {
+    backend: Arc<dyn Backend>,
+    session: *mut mfxSession,
+    uid: mfxPluginUID,
+}
+
+impl Session {
+    /// Creates a session on an implementation of type `implementation`
+    /// supporting at least API `version` and able to encode `codec`.
+    ///
+    /// oneVPL runtimes are asked through the dispatcher for a matching
+    /// implementation; Media SDK 1.x runtimes, which have no dispatcher, fall
+    /// back to `MFXInit`, where the codec is only checked at encoder setup.
+    pub fn new(
+        backend: Arc<dyn Backend>,
+        implementation: mfxIMPL,
+        version: &mfxVersion,
+        codec: mfxU32,
+    ) -> Result<Session> {
+        let loader = match Loader::new(&backend) {
+            Some(loader) => loader,
+            None => return Session::init(backend, implementation, version),
+        };
+        if let Some(impl_type) = impl_type(implementation) {
+            loader.add_filter(IMPL_FILTER, impl_type)?;
+        }
+        loader.add_filter(CODEC_FILTER, codec)?;
+        let version = (version.Major as mfxU32) << 16 | version.Minor as mfxU32;
+        loader.add_filter(API_VERSION_FILTER, version)?;
+
+        // the filters leave only suitable implementations, the first of
+        // which is the dispatcher's preferred one
+        loader.impl_name(0)?;
+        Session::create(backend, Rc::new(loader), 0)
+    }
+
+    /// Creates a session on the `index`th implementation enumerated by
+    /// `loader`.
+    pub fn create(backend: Arc<dyn Backend>, loader: Rc<Loader>, index: mfxU32) -> Result<Session> {
+        let mut raw: *mut mfxSession = ptr::null_mut();
+        check("MFXCreateSession", unsafe {
+            backend.create_session(loader.raw, index, &mut raw)
+        })?;
+        Ok(Session::from_raw(backend, raw, Some(loader)))
+    }
+
+    /// Initializes a session the Media SDK 1.x way, without a dispatcher.
+    pub fn init(
+        backend: Arc<dyn Backend>,
+        implementation: mfxIMPL,
+        version: &mfxVersion,
+    ) -> Result<Session> {
+        let mut raw: *mut mfxSession = ptr::null_mut();
+        check("MFXInit", unsafe {
+            backend.init(implementation, version, &mut raw)
+        })?;
+        Ok(Session::from_raw(backend, raw, None))
+    }
+
+    fn from_raw(
+        backend: Arc<dyn Backend>,
+        raw: *mut mfxSession,
+        loader: Option<Rc<Loader>>,
+    ) -> Session {
+        Session {
+            backend,
+            raw,
+            vpp: None,
+            encode: None,
+            plugins: Vec::new(),
+            loader,
+        }
+    }
+
+    /// Loads the plugin Media SDK 1.x runtimes need to encode `codec`, if
+    /// any. oneVPL implementations have every encoder built in.
+    pub fn load_encoder_plugin(&mut self, codec: mfxU32) {
+        if codec != MFX_CODEC_HEVC || self.loader.is_some() {
+            return;
+        }
+        // Runtimes with HEVC built in may not know the plugin, so a failure
+        // here is left for the encoder setup to report.
+        let uid = MFX_PLUGINID_HEVCE_HW;
+        let loaded = check("MFXVideoUSER_Load", unsafe {
+            self.backend.user_load(self.raw, &uid, 1)
+        });
+        if loaded.is_ok() {
+            self.plugins.push(PluginComponent {
+                backend: self.backend.clone(),
+                session: self.raw,
+                uid,
+            });
+        }
+    }
+
+    pub fn query_version(&self) -> Result<mfxVersion> {
+        let mut version = mfxVersion::new(0, 0);
+        check("MFXQueryVersion", unsafe {
+            self.backend.query_version(self.raw, &mut version)
+        })?;
+        Ok(version)
+    }
+
+    pub fn query_impl(&self) -> Result<mfxIMPL> {
+        let mut implementation: mfxIMPL = 0;
+        check("MFXQueryIMPL", unsafe {
+            self.backend.query_impl(self.raw, &mut implementation)
+        })?;
+        Ok(implementation)
+    }
+
+    pub fn sync_operation(&self, syncp: mfxSyncPoint, wait: mfxU32) -> Result<Option<MfxWarning>> {
+        check("MFXVideoCORE_SyncOperation", unsafe {
+            self.backend.sync_operation(self.raw, syncp, wait)
+        })
+    }
+
+    pub fn encode_query(&self, par: &mut mfxVideoParam) -> Result<Option<MfxWarning>> {
+        let par: *mut mfxVideoParam = par;
+        check("MFXVideoENCODE_Query", unsafe {
+            self.backend.encode_query(self.raw, par, par)
+        })
+    }
+
+    pub fn encode_query_io_surf(&self, par: &mfxVideoParam) -> Result<mfxFrameAllocRequest> {
+        let mut request = mfxFrameAllocRequest::new();
+        check("MFXVideoENCODE_QueryIOSurf", unsafe {
+            self.backend
+                .encode_query_io_surf(self.raw, par, &mut request)
+        })?;
+        Ok(request)
+    }
+
+    pub fn init_encode(&mut self, par: &mfxVideoParam) -> Result<Option<MfxWarning>> {
+        let warning = check("MFXVideoENCODE_Init", unsafe {
+            self.backend.encode_init(self.raw, par)
+        })?;
+
+        self.encode = Some(EncodeComponent {
+            backend: self.backend.clone(),
+            session: self.raw,
+        });
+        Ok(warning)
+    }
+
+    /// Applies `par` to the initialized encoder, dropping the frames it
+    /// holds. Fails with `MfxError::IncompatibleVideoParam` when the change
+    /// needs the encoder closed and initialized again.
+    pub fn encode_reset(&self, par: &mfxVideoParam) -> Result<Option<MfxWarning>> {
+        check("MFXVideoENCODE_Reset", unsafe {
+            self.backend.encode_reset(self.raw, par)
+        })
+    }
+
+    pub fn close_encode(&mut self) {
+        self.encode = None;
+    }
+
+    pub fn encode_get_video_param(&self) -> Result<mfxVideoParam> {
+        let mut par = mfxVideoParam::new();
+        check("MFXVideoENCODE_GetVideoParam", unsafe {
+            self.backend.encode_get_video_param(self.raw, &mut par)
+        })?;
+        Ok(par)
+    }
+
+    /// Same as [`Session::encode_get_video_param`], also filling in the
+    /// coding option buffers.
+    pub fn encode_get_coding_options(
+        &self,
+        co: &mut mfxExtCodingOption,
+        co2: &mut mfxExtCodingOption2,
+        co3: &mut mfxExtCodingOption3,
+    ) -> Result<mfxVideoParam> {
+        // cast from the whole buffers, so the runtime may write past the headers
+        let ext_params: [*const mfxExtBuffer; 3] = [
+            co as *mut mfxExtCodingOption as *const mfxExtBuffer,
+            co2 as *mut mfxExtCodingOption2 as *const mfxExtBuffer,
+            co3 as *mut mfxExtCodingOption3 as *const mfxExtBuffer,
+        ];
+        let mut par = mfxVideoParam::new();
+        par.ExtParam = ext_params.as_ptr();
+        par.NumExtParam = ext_params.len() as mfxU16;
+        check("MFXVideoENCODE_GetVideoParam", unsafe {
+            self.backend.encode_get_video_param(self.raw, &mut par)
+        })?;
+        // only referenced for the duration of the call
+        par.ExtParam = ptr::null();
+        par.NumExtParam = 0;
+        Ok(par)
+    }
+
+    /// The encoder keeps the surface locked and reads `ctrl` until it has
+    /// encoded the frame, and the driver writes `bs` until `syncp` is
+    /// synchronized, so none of them may move or be freed before then.
+    pub fn encode_frame_async(
+        &self,
+        ctrl: Option<&mfxEncodeCtrl>,
+        surface: Option<&mfxFrameSurface1>,
+        bs: &mut mfxBitstream,
+        syncp: &mut mfxSyncPoint,
+    ) -> Result<Option<MfxWarning>> {
+        let ctrl = ctrl.map_or(ptr::null(), |c| c as *const _);
+        let surface = surface.map_or(ptr::null(), |s| s as *const _);
+        check("MFXVideoENCODE_EncodeFrameAsync", unsafe {
+            self.backend
+                .encode_frame_async(self.raw, ctrl, surface, bs, syncp)
+        })
+    }
+
+    pub fn vpp_query_io_surf(&self, par: &mfxVideoParam) -> Result<[mfxFrameAllocRequest; 2]> {
+        let mut request = [mfxFrameAllocRequest::new(), mfxFrameAllocRequest::new()];
+        check("MFXVideoVPP_QueryIOSurf", unsafe {
+            self.backend
+                .vpp_query_io_surf(self.raw, par, request.as_mut_ptr())
+        })?;
+        Ok(request)
+    }
+
+    pub fn init_vpp(&mut self, par: &mut mfxVideoParam) -> Result<Option<MfxWarning>> {
+        let warning = check("MFXVideoVPP_Init", unsafe {
+            self.backend.vpp_init(self.raw, par)
+        })?;
+
+        self.vpp = Some(VppComponent {
+            backend: self.backend.clone(),
+            session: self.raw,
+        });
+        Ok(warning)
+    }
+
+    pub fn vpp_reset(&self, par: &mut mfxVideoParam) -> Result<Option<MfxWarning>> {
+        check("MFXVideoVPP_Reset", unsafe {
+            self.backend.vpp_reset(self.raw, par)
+        })
+    }
+
+    /// Same lifetime rules as `encode_frame_async`: both surfaces stay in
+    /// use until `syncp` is synchronized.
+    pub fn vpp_run_frame_async(
+        &self,
+        input: Option<&mfxFrameSurface1>,
+        output: &mut mfxFrameSurface1,
+        syncp: &mut mfxSyncPoint,
+    ) -> Result<Option<MfxWarning>> {
+        let input = input.map_or(ptr::null(), |s| s as *const _);
+        check("MFXVideoVPP_RunFrameVPPAsync", unsafe {
+            self.backend
+                .vpp_run_frame_async(self.raw, input, output, ptr::null(), syncp)
+        })
+    }
+}
+
+/// Implementation type the dispatcher is filtered on, `None` for any.
+fn impl_type(implementation: mfxIMPL) -> Option<mfxImplType> {
+    // the via flags in the upper bits only select the device interface
+    match implementation & 0xff {
+        MFX_IMPL_SOFTWARE => Some(MFX_IMPL_TYPE_SOFTWARE),
+        MFX_IMPL_AUTO | MFX_IMPL_AUTO_ANY => None,
+        _ => Some(MFX_IMPL_TYPE_HARDWARE),
+    }
+}
+
+impl Loader {
+    /// Returns `None` when the runtime has no oneVPL dispatcher.
+    pub fn new(backend: &Arc<dyn Backend>) -> Option<Loader> {
+        let raw = unsafe { backend.load() };
+        if raw.is_null() {
+            return None;
+        }
+        Some(Loader {
+            backend: backend.clone(),
+            raw,
+        })
+    }
+
+    /// Only implementations whose `name` property equals `value` are
+    /// enumerated from now on. `name` is NUL-terminated.
+    pub fn add_filter(&self, name: &'static [u8], value: mfxU32) -> Result<()> {
+        let config = unsafe { self.backend.create_config(self.raw) };
+        if config.is_null() {
+            return Err(Error::Mfx {
+                function: "MFXCreateConfig",
+                error: MfxError::NullPtr,
+            });
+        }
+        check("MFXSetConfigFilterProperty", unsafe {
+            self.backend
+                .set_config_filter_property(config, name.as_ptr(), mfxVariant::u32(value))
+        })?;
+        Ok(())
+    }
+
+    /// Name of the `index`th implementation matching the filters, copied out
+    /// of its description before the dispatcher releases it. Fails with
+    /// `MfxError::NotFound` past the last one.
+    pub fn impl_name(&self, index: mfxU32) -> Result<String> {
+        let mut hdl: mfxHDL = ptr::null();
+        check("MFXEnumImplementations", unsafe {
+            self.backend.enum_implementations(
+                self.raw,
+                index,
+                MFX_IMPLCAPS_IMPLDESCSTRUCTURE,
+                &mut hdl,
+            )
+        })?;
+        // nothing may point into the description once it is released
+        let name = unsafe {
+            let description = &*(hdl as *const mfxImplDescription);
+            CStr::from_ptr(description.ImplName.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        };
+        unsafe { self.backend.release_impl_description(self.raw, hdl) };
+        Ok(name)
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        unsafe { self.backend.unload(self.raw) };
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // components are closed in reverse order of initialization
+        self.encode.take();
+        self.vpp.take();
+        self.plugins.clear();
+        unsafe { self.backend.close(self.raw) };
+    }
+}
+
+impl Drop for EncodeComponent {
+    fn drop(&mut self) {
+        unsafe { self.backend.encode_close(self.session) };
+    }
+}
+
+impl Drop for PluginComponent {
+    fn drop(&mut self) {
+        unsafe { self.backend.user_unload(self.session, &self.uid) };
+    }
+}
+
+impl Drop for VppComponent {
+    fn drop(&mut self) {
+        unsafe { self.backend.vpp_close(self.session) };
+    }
+}
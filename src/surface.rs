@@ -0,0 +1,204 @@
+use std::alloc::{self, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::rc::Rc;
+
+use crate::sys::*;
+
+// Frame memory starts on a page boundary, as the driver maps system memory
+// surfaces page by page.
+const SURFACE_ALIGNMENT: usize = 4096;
+
+/// System memory surfaces of one frame format, handed out through
+/// [`SurfaceLease`]s. The pool starts with the `NumFrameMin` surfaces the
+/// component asked for and grows up to `NumFrameSuggested`. Surfaces are
+/// allocated one by one and never move, since the runtime keeps pointers to
+/// the ones it holds.
+pub(crate) struct SurfacePool {
+    info: mfxFrameInfo,
+    slots: Vec<Rc<Slot>>,
+    capacity: usize,
+}
+
+/// Exclusive use of a pool surface, which goes back to the pool when the
+/// lease is dropped. A surface the runtime still holds stays locked until
+/// its operation completes.
+pub(crate) struct SurfaceLease {
+    slot: Rc<Slot>,
+    index: usize,
+}
+
+struct Slot {
+    surface: UnsafeCell<mfxFrameSurface1>,
+    leased: Cell<bool>,
+    _buffer: AlignedBuffer,
+}
+
+struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl SurfacePool {
+    pub(crate) fn new(info: &mfxFrameInfo, request: &mfxFrameAllocRequest) -> SurfacePool {
+        let initial = request.NumFrameMin.max(1) as usize;
+        let mut pool = SurfacePool {
+            info: *info,
+            slots: Vec::new(),
+            capacity: initial.max(request.NumFrameSuggested as usize),
+        };
+        for _ in 0..initial {
+            pool.grow();
+        }
+        pool
+    }
+
+    /// Number of surfaces the pool may grow to.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Leases a surface neither leased nor locked by the runtime, allocating
+    /// one if all are busy and the pool is not at capacity yet. None when
+    /// every surface is in use.
+    pub(crate) fn lease(&mut self) -> Option<SurfaceLease> {
+        let free = self.slots.iter().position(|slot| slot.is_free());
+        let index = match free {
+            Some(index) => index,
+            None if self.slots.len() < self.capacity => self.grow(),
+            None => return None,
+        };
+        let slot = self.slots[index].clone();
+        slot.leased.set(true);
+        Some(SurfaceLease { slot, index })
+    }
+
+    /// Describes the frame of `info` in the surfaces, which were allocated
+    /// for a frame at least as large. Panics if any of them is leased or
+    /// locked by the runtime.
+    pub(crate) fn set_frame(&mut self, info: &mfxFrameInfo) {
+        // a lease or the runtime may be writing to the surface
+        assert!(
+            self.slots.iter().all(|slot| slot.is_free()),
+            "surfaces changed while in use"
+        );
+        self.info.CropW = info.CropW;
+        self.info.CropH = info.CropH;
+        self.info.FrameRateExtN = info.FrameRateExtN;
+        self.info.FrameRateExtD = info.FrameRateExtD;
+        for slot in &self.slots {
+            let surface = unsafe { &mut *slot.surface.get() };
+            surface.Info = self.info;
+        }
+    }
+
+    fn grow(&mut self) -> usize {
+        self.slots.push(Rc::new(Slot::new(&self.info)));
+        self.slots.len() - 1
+    }
+}
+
+impl SurfaceLease {
+    /// Position of the surface in its pool, below [`SurfacePool::capacity`].
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn surface(&self) -> &mfxFrameSurface1 {
+        unsafe { &*self.slot.surface.get() }
+    }
+
+    pub(crate) fn surface_mut(&mut self) -> &mut mfxFrameSurface1 {
+        unsafe { &mut *self.slot.surface.get() }
+    }
+}
+
+impl Drop for SurfaceLease {
+    fn drop(&mut self) {
+        self.slot.leased.set(false);
+    }
+}
+
+impl Slot {
+    fn new(info: &mfxFrameInfo) -> Slot {
+        let (pitch, size) = layout(info);
+        let height = align32(info.Height as u32) as usize;
+        let buffer = AlignedBuffer::new(size);
+
+        let mut surface = mfxFrameSurface1::new();
+        surface.Info = *info;
+        let base = buffer.ptr;
+        let data = &mut surface.Data;
+        unsafe {
+            match info.FourCC {
+                // packed V, U, Y, A
+                MFX_FOURCC_AYUV => {
+                    data.V = base;
+                    data.UV = base.add(1);
+                    data.Y = base.add(2);
+                    data.A = base.add(3);
+                }
+                // interleaved U, V with 2 bytes per sample
+                MFX_FOURCC_P010 => {
+                    data.Y = base;
+                    data.UV = base.add(pitch * height);
+                    data.V = data.UV.add(2);
+                }
+                MFX_FOURCC_NV12 => {
+                    data.Y = base;
+                    data.UV = base.add(pitch * height);
+                    data.V = data.UV.add(1);
+                }
+                _ => {
+                    data.Y = base;
+                    data.UV = base.add(pitch * height);
+                    data.V = data.UV.add(pitch * height / 4);
+                }
+            }
+        }
+        data.PitchHigh = (pitch >> 16) as u16;
+        data.PitchLow = pitch as u16;
+
+        Slot {
+            surface: UnsafeCell::new(surface),
+            leased: Cell::new(false),
+            _buffer: buffer,
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        let surface = unsafe { &*self.surface.get() };
+        !self.leased.get() && surface.Data.Locked == 0
+    }
+}
+
+impl AlignedBuffer {
+    fn new(size: usize) -> AlignedBuffer {
+        let layout = Layout::from_size_align(size.max(1), SURFACE_ALIGNMENT).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuffer { ptr, layout }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+// Pitch and size in bytes of a surface of this format at its aligned size.
+fn layout(info: &mfxFrameInfo) -> (usize, usize) {
+    let width = align32(info.Width as u32) as usize;
+    let height = align32(info.Height as u32) as usize;
+    match info.FourCC {
+        MFX_FOURCC_P010 => (width * 2, width * 2 * height * 3 / 2),
+        MFX_FOURCC_AYUV => (width * 4, width * 4 * height),
+        _ => (width, width * height * 3 / 2),
+    }
+}
+
+fn align32(x: u32) -> u32 {
+    (x + 31) & !31
+}
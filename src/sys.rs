@@ -0,0 +1,1433 @@
+//! Raw Intel Media SDK types and entry points.
+//!
+//! Layouts follow `mfxstructures.h`; custom [`Backend`](crate::Backend)
+//! implementations receive and fill these structures directly.
+
+#![allow(
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    dead_code
+)]
+#![allow(clippy::new_without_default)]
+
+use std::mem;
+use std::ptr;
+
+pub type mfxU8 = u8;
+pub type mfxI16 = i16;
+pub type mfxU16 = u16;
+pub type mfxU32 = u32;
+pub type mfxI32 = i32;
+pub type mfxU64 = u64;
+pub type mfxI64 = i64;
+pub type mfxIMPL = mfxI32;
+pub type mfxStatus = mfxI32;
+pub type mfxSession = libc::c_void;
+pub type mfxHDL = *const libc::c_void;
+pub type mfxMemId = mfxHDL;
+pub type mfxSyncPoint = *const libc::c_void;
+pub type mfxChar = libc::c_char;
+pub type mfxLoader = *mut libc::c_void;
+pub type mfxConfig = *mut libc::c_void;
+pub type mfxImplType = mfxU32;
+pub type mfxAccelerationMode = mfxU32;
+pub type mfxVariantType = mfxU32;
+pub type mfxImplCapsDeliveryFormat = mfxU32;
+pub type mfxResourceType = mfxU32;
+
+pub const MFX_IMPL_AUTO: mfxIMPL = 0x0000;
+pub const MFX_IMPL_SOFTWARE: mfxIMPL = 0x0001;
+pub const MFX_IMPL_HARDWARE: mfxIMPL = 0x0002;
+pub const MFX_IMPL_AUTO_ANY: mfxIMPL = 0x0003;
+pub const MFX_IMPL_HARDWARE_ANY: mfxIMPL = 0x0004;
+pub const MFX_IMPL_HARDWARE2: mfxIMPL = 0x0005;
+pub const MFX_IMPL_HARDWARE3: mfxIMPL = 0x0006;
+pub const MFX_IMPL_HARDWARE4: mfxIMPL = 0x0007;
+
+pub const MFX_IMPL_TYPE_SOFTWARE: mfxImplType = 0x0001;
+pub const MFX_IMPL_TYPE_HARDWARE: mfxImplType = 0x0002;
+
+pub const MFX_VARIANT_TYPE_UNSET: mfxVariantType = 0;
+pub const MFX_VARIANT_TYPE_U8: mfxVariantType = 1;
+pub const MFX_VARIANT_TYPE_I8: mfxVariantType = 2;
+pub const MFX_VARIANT_TYPE_U16: mfxVariantType = 3;
+pub const MFX_VARIANT_TYPE_I16: mfxVariantType = 4;
+pub const MFX_VARIANT_TYPE_U32: mfxVariantType = 5;
+pub const MFX_VARIANT_TYPE_I32: mfxVariantType = 6;
+pub const MFX_VARIANT_TYPE_U64: mfxVariantType = 7;
+pub const MFX_VARIANT_TYPE_I64: mfxVariantType = 8;
+pub const MFX_VARIANT_TYPE_F32: mfxVariantType = 9;
+pub const MFX_VARIANT_TYPE_F64: mfxVariantType = 10;
+pub const MFX_VARIANT_TYPE_PTR: mfxVariantType = 11;
+
+pub const MFX_IMPLCAPS_IMPLDESCSTRUCTURE: mfxImplCapsDeliveryFormat = 1;
+
+pub const MFX_IMPL_NAME_LEN: usize = 32;
+pub const MFX_STRFIELD_LEN: usize = 128;
+
+pub const MFX_ERR_NONE: mfxStatus = 0;
+pub const MFX_ERR_UNKNOWN: mfxStatus = -1;
+pub const MFX_ERR_NULL_PTR: mfxStatus = -2;
+pub const MFX_ERR_UNSUPPORTED: mfxStatus = -3;
+pub const MFX_ERR_MEMORY_ALLOC: mfxStatus = -4;
+pub const MFX_ERR_NOT_ENOUGH_BUFFER: mfxStatus = -5;
+pub const MFX_ERR_INVALID_HANDLE: mfxStatus = -6;
+pub const MFX_ERR_LOCK_MEMORY: mfxStatus = -7;
+pub const MFX_ERR_NOT_INITIALIZED: mfxStatus = -8;
+pub const MFX_ERR_NOT_FOUND: mfxStatus = -9;
+pub const MFX_ERR_MORE_DATA: mfxStatus = -10;
+pub const MFX_ERR_MORE_SURFACE: mfxStatus = -11;
+pub const MFX_ERR_ABORTED: mfxStatus = -12;
+pub const MFX_ERR_DEVICE_LOST: mfxStatus = -13;
+pub const MFX_ERR_INCOMPATIBLE_VIDEO_PARAM: mfxStatus = -14;
+pub const MFX_ERR_INVALID_VIDEO_PARAM: mfxStatus = -15;
+pub const MFX_ERR_UNDEFINED_BEHAVIOR: mfxStatus = -16;
+pub const MFX_ERR_DEVICE_FAILED: mfxStatus = -17;
+pub const MFX_ERR_MORE_BITSTREAM: mfxStatus = -18;
+pub const MFX_ERR_INCOMPATIBLE_AUDIO_PARAM: mfxStatus = -19;
+pub const MFX_ERR_INVALID_AUDIO_PARAM: mfxStatus = -20;
+pub const MFX_ERR_GPU_HANG: mfxStatus = -21;
+pub const MFX_ERR_REALLOC_SURFACE: mfxStatus = -22;
+pub const MFX_ERR_RESOURCE_MAPPED: mfxStatus = -23;
+pub const MFX_ERR_NOT_IMPLEMENTED: mfxStatus = -24;
+
+pub const MFX_WRN_IN_EXECUTION: mfxStatus = 1;
+pub const MFX_WRN_DEVICE_BUSY: mfxStatus = 2;
+pub const MFX_WRN_VIDEO_PARAM_CHANGED: mfxStatus = 3;
+pub const MFX_WRN_PARTIAL_ACCELERATION: mfxStatus = 4;
+pub const MFX_WRN_INCOMPATIBLE_VIDEO_PARAM: mfxStatus = 5;
+pub const MFX_WRN_VALUE_NOT_CHANGED: mfxStatus = 6;
+pub const MFX_WRN_OUT_OF_RANGE: mfxStatus = 7;
+pub const MFX_WRN_FILTER_SKIPPED: mfxStatus = 10;
+pub const MFX_WRN_INCOMPATIBLE_AUDIO_PARAM: mfxStatus = 11;
+pub const MFX_ERR_NONE_PARTIAL_OUTPUT: mfxStatus = 12;
+pub const MFX_WRN_ALLOC_TIMEOUT_EXPIRED: mfxStatus = 13;
+
+pub const MFX_TARGETUSAGE_1: u16 = 1;
+pub const MFX_TARGETUSAGE_2: u16 = 2;
+pub const MFX_TARGETUSAGE_3: u16 = 3;
+pub const MFX_TARGETUSAGE_4: u16 = 4;
+pub const MFX_TARGETUSAGE_5: u16 = 5;
+pub const MFX_TARGETUSAGE_6: u16 = 6;
+pub const MFX_TARGETUSAGE_7: u16 = 7;
+
+pub const MFX_TARGETUSAGE_UNKNOWN: u16 = 0;
+pub const MFX_TARGETUSAGE_BEST_QUALITY: u16 = MFX_TARGETUSAGE_1;
+pub const MFX_TARGETUSAGE_BALANCED: u16 = MFX_TARGETUSAGE_4;
+pub const MFX_TARGETUSAGE_BEST_SPEED: u16 = MFX_TARGETUSAGE_7;
+
+pub const MFX_CODEC_AVC: mfxU32 = 0x20435641;
+pub const MFX_CODEC_HEVC: mfxU32 = 0x43564548;
+pub const MFX_CODEC_MPEG2: mfxU32 = 0x3247504d;
+pub const MFX_CODEC_VP9: mfxU32 = 0x20395056;
+pub const MFX_CODEC_AV1: mfxU32 = 0x20315641;
+pub const MFX_CODEC_JPEG: mfxU32 = 0x4745504a;
+
+pub const MFX_FOURCC_NV12: mfxU32 = 0x3231564e;
+pub const MFX_FOURCC_YV12: mfxU32 = 0x32315659;
+pub const MFX_FOURCC_YUY2: mfxU32 = 0x32595559;
+pub const MFX_FOURCC_RGB4: mfxU32 = 0x34424752;
+pub const MFX_FOURCC_P010: mfxU32 = 0x30313050;
+pub const MFX_FOURCC_AYUV: mfxU32 = 0x56555941;
+pub const MFX_FOURCC_Y210: mfxU32 = 0x30313259;
+pub const MFX_FOURCC_Y410: mfxU32 = 0x30313459;
+
+pub const MFX_PROFILE_UNKNOWN: u16 = 0;
+pub const MFX_PROFILE_AVC_CONSTRAINT_SET1: u16 = 0x100 << 1;
+pub const MFX_PROFILE_AVC_BASELINE: u16 = 66;
+pub const MFX_PROFILE_AVC_MAIN: u16 = 77;
+pub const MFX_PROFILE_AVC_EXTENDED: u16 = 88;
+pub const MFX_PROFILE_AVC_HIGH: u16 = 100;
+pub const MFX_PROFILE_AVC_HIGH10: u16 = 110;
+pub const MFX_PROFILE_AVC_HIGH_422: u16 = 122;
+pub const MFX_PROFILE_AVC_CONSTRAINED_BASELINE: u16 =
+    MFX_PROFILE_AVC_BASELINE + MFX_PROFILE_AVC_CONSTRAINT_SET1;
+
+pub const MFX_LEVEL_UNKNOWN: u16 = 0;
+pub const MFX_LEVEL_AVC_1: u16 = 10;
+pub const MFX_LEVEL_AVC_1b: u16 = 9;
+pub const MFX_LEVEL_AVC_11: u16 = 11;
+pub const MFX_LEVEL_AVC_12: u16 = 12;
+pub const MFX_LEVEL_AVC_13: u16 = 13;
+pub const MFX_LEVEL_AVC_2: u16 = 20;
+pub const MFX_LEVEL_AVC_21: u16 = 21;
+pub const MFX_LEVEL_AVC_22: u16 = 22;
+pub const MFX_LEVEL_AVC_3: u16 = 30;
+pub const MFX_LEVEL_AVC_31: u16 = 31;
+pub const MFX_LEVEL_AVC_32: u16 = 32;
+pub const MFX_LEVEL_AVC_4: u16 = 40;
+pub const MFX_LEVEL_AVC_41: u16 = 41;
+pub const MFX_LEVEL_AVC_42: u16 = 42;
+pub const MFX_LEVEL_AVC_5: u16 = 50;
+pub const MFX_LEVEL_AVC_51: u16 = 51;
+pub const MFX_LEVEL_AVC_52: u16 = 52;
+pub const MFX_LEVEL_AVC_6: u16 = 60;
+pub const MFX_LEVEL_AVC_61: u16 = 61;
+pub const MFX_LEVEL_AVC_62: u16 = 62;
+
+pub const MFX_PROFILE_HEVC_MAIN: u16 = 1;
+pub const MFX_PROFILE_HEVC_MAIN10: u16 = 2;
+pub const MFX_PROFILE_HEVC_MAINSP: u16 = 3;
+pub const MFX_PROFILE_HEVC_REXT: u16 = 4;
+
+pub const MFX_PROFILE_VP9_0: u16 = 1;
+pub const MFX_PROFILE_VP9_1: u16 = 2;
+pub const MFX_PROFILE_VP9_2: u16 = 3;
+pub const MFX_PROFILE_VP9_3: u16 = 4;
+
+pub const MFX_PROFILE_AV1_MAIN: u16 = 1;
+pub const MFX_PROFILE_AV1_HIGH: u16 = 2;
+pub const MFX_PROFILE_AV1_PRO: u16 = 3;
+
+pub const MFX_PROFILE_JPEG_BASELINE: u16 = 1;
+
+pub const MFX_RATECONTROL_CBR: u16 = 1;
+pub const MFX_RATECONTROL_VBR: u16 = 2;
+pub const MFX_RATECONTROL_CQP: u16 = 3;
+pub const MFX_RATECONTROL_AVBR: u16 = 4;
+pub const MFX_RATECONTROL_LA: u16 = 8;
+pub const MFX_RATECONTROL_ICQ: u16 = 9;
+pub const MFX_RATECONTROL_VCM: u16 = 10;
+pub const MFX_RATECONTROL_LA_ICQ: u16 = 11;
+pub const MFX_RATECONTROL_LA_HRD: u16 = 13;
+pub const MFX_RATECONTROL_QVBR: u16 = 14;
+
+pub const MFX_CODINGOPTION_UNKNOWN: u16 = 0x00;
+pub const MFX_CODINGOPTION_ON: u16 = 0x10;
+pub const MFX_CODINGOPTION_OFF: u16 = 0x20;
+pub const MFX_CODINGOPTION_ADAPTIVE: u16 = 0x30;
+
+pub const MFX_GOP_CLOSED: u16 = 1;
+pub const MFX_GOP_STRICT: u16 = 2;
+
+pub const MFX_SCANTYPE_UNKNOWN: u16 = 0;
+pub const MFX_SCANTYPE_INTERLEAVED: u16 = 1;
+pub const MFX_SCANTYPE_NONINTERLEAVED: u16 = 2;
+
+pub const MFX_CHROMAFORMAT_MONOCHROME: u16 = 0;
+pub const MFX_CHROMAFORMAT_YUV420: u16 = 1;
+pub const MFX_CHROMAFORMAT_YUV422: u16 = 2;
+pub const MFX_CHROMAFORMAT_YUV444: u16 = 3;
+
+pub const MFX_PICSTRUCT_UNKNOWN: u16 = 0;
+pub const MFX_PICSTRUCT_PROGRESSIVE: u16 = 1;
+
+pub const MFX_IOPATTERN_IN_VIDEO_MEMORY: u16 = 0x01;
+pub const MFX_IOPATTERN_IN_SYSTEM_MEMORY: u16 = 0x02;
+pub const MFX_IOPATTERN_OUT_VIDEO_MEMORY: u16 = 0x10;
+pub const MFX_IOPATTERN_OUT_SYSTEM_MEMORY: u16 = 0x20;
+
+pub const MFX_FRAMETYPE_UNKNOWN: u16 = 0x0000;
+pub const MFX_FRAMETYPE_I: u16 = 0x0001;
+pub const MFX_FRAMETYPE_P: u16 = 0x0002;
+pub const MFX_FRAMETYPE_B: u16 = 0x0004;
+pub const MFX_FRAMETYPE_S: u16 = 0x0008;
+pub const MFX_FRAMETYPE_REF: u16 = 0x0040;
+pub const MFX_FRAMETYPE_IDR: u16 = 0x0080;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxVersion {
+    pub Minor: mfxU16,
+    pub Major: mfxU16,
+}
+
+impl mfxVersion {
+    pub const fn new(Major: mfxU16, Minor: mfxU16) -> Self {
+        mfxVersion { Major, Minor }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxFrameIdStruct1 {
+    pub DependencyId: mfxU16,
+    pub QualityId: mfxU16,
+}
+
+impl mfxFrameIdStruct1 {
+    pub fn new() -> Self {
+        mfxFrameIdStruct1 {
+            DependencyId: 0,
+            QualityId: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxFrameIdStruct2 {
+    pub ViewId: mfxU16,
+}
+
+impl mfxFrameIdStruct2 {
+    pub fn new() -> Self {
+        mfxFrameIdStruct2 { ViewId: 0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxFrameIdUnion {
+    pub s1: mfxFrameIdStruct1,
+    pub s2: mfxFrameIdStruct2,
+}
+
+impl mfxFrameIdUnion {
+    pub fn new() -> Self {
+        mfxFrameIdUnion {
+            s1: mfxFrameIdStruct1::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxFrameId {
+    pub TemporalId: mfxU16,
+    pub PriorityId: mfxU16,
+    pub u: mfxFrameIdUnion,
+}
+
+impl mfxFrameId {
+    pub fn new() -> Self {
+        mfxFrameId {
+            TemporalId: 0,
+            PriorityId: 0,
+            u: mfxFrameIdUnion::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxFrameInfo {
+    pub reserved: [mfxU32; 4],
+    pub reserved4: mfxU16,
+    pub BitDepthLuma: mfxU16,
+    pub BitDepthChroma: mfxU16,
+    pub Shift: mfxU16,
+
+    pub FrameId: mfxFrameId,
+    pub FourCC: mfxU32,
+
+    // TODO: union, frame paramaters, omit buffer parameters, both 96 bits
+    pub Width: mfxU16,
+    pub Height: mfxU16,
+    pub CropX: mfxU16,
+    pub CropY: mfxU16,
+    pub CropW: mfxU16,
+    pub CropH: mfxU16,
+
+    pub FrameRateExtN: mfxU32,
+    pub FrameRateExtD: mfxU32,
+    pub reserved3: mfxU16,
+
+    pub AspectRatioW: mfxU16,
+    pub AspectRatioH: mfxU16,
+
+    pub PicStruct: mfxU16,
+    pub ChromaFormat: mfxU16,
+    pub reserved2: mfxU16,
+}
+
+impl mfxFrameInfo {
+    pub fn new() -> Self {
+        mfxFrameInfo {
+            reserved: [0; 4],
+            reserved4: 0,
+            BitDepthLuma: 0,
+            BitDepthChroma: 0,
+            Shift: 0,
+            FrameId: mfxFrameId::new(),
+            FourCC: 0,
+            Width: 0,
+            Height: 0,
+            CropX: 0,
+            CropY: 0,
+            CropW: 0,
+            CropH: 0,
+            FrameRateExtN: 0,
+            FrameRateExtD: 0,
+            reserved3: 0,
+            AspectRatioW: 0,
+            AspectRatioH: 0,
+            PicStruct: 0,
+            ChromaFormat: 0,
+            reserved2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxInfoMFXUnion1 {
+    pub InitialDelayInKB: mfxU16,
+    pub QPI: mfxU16,
+    pub Accuracy: mfxU16,
+}
+
+impl mfxInfoMFXUnion1 {
+    pub fn new() -> Self {
+        mfxInfoMFXUnion1 {
+            InitialDelayInKB: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxInfoMFXUnion2 {
+    pub TargetKbps: mfxU16,
+    pub QPP: mfxU16,
+    pub ICQQuality: mfxU16,
+}
+
+impl mfxInfoMFXUnion2 {
+    pub fn new() -> Self {
+        mfxInfoMFXUnion2 { TargetKbps: 0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxInfoMFXUnion3 {
+    pub MaxKbps: mfxU16,
+    pub QPB: mfxU16,
+    pub Convergence: mfxU16,
+}
+
+impl mfxInfoMFXUnion3 {
+    pub fn new() -> Self {
+        mfxInfoMFXUnion3 { MaxKbps: 0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxInfoMFX {
+    pub reserved: [mfxU32; 7],
+    pub LowPower: mfxU16,
+    pub BRCParamMultiplier: mfxU16,
+    pub FrameInfo: mfxFrameInfo,
+    pub CodecId: mfxU32,
+    pub CodecProfile: mfxU16,
+    pub CodecLevel: mfxU16,
+    pub NumThread: mfxU16,
+
+    //  only include encoding options
+    pub TargetUsage: mfxU16,
+    pub GopPicSize: mfxU16,
+    pub GopRefDist: mfxU16,
+    pub GopOptFlag: mfxU16,
+    pub IdrInterval: mfxU16,
+    pub RateControlMethod: mfxU16,
+    pub u1: mfxInfoMFXUnion1,
+    pub BufferSizeInKB: mfxU16,
+    pub u2: mfxInfoMFXUnion2,
+    pub u3: mfxInfoMFXUnion3,
+    pub NumSlice: mfxU16,
+    pub NumRefFrame: mfxU16,
+    pub EncodedOrder: mfxU16,
+}
+
+impl mfxInfoMFX {
+    pub fn new() -> Self {
+        mfxInfoMFX {
+            reserved: [0; 7],
+            LowPower: 0,
+            BRCParamMultiplier: 0,
+            FrameInfo: mfxFrameInfo::new(),
+            CodecId: 0,
+            CodecProfile: 0,
+            CodecLevel: 0,
+            NumThread: 0,
+            TargetUsage: 0,
+            GopPicSize: 0,
+            GopRefDist: 0,
+            GopOptFlag: 0,
+            IdrInterval: 0,
+            RateControlMethod: 0,
+            u1: mfxInfoMFXUnion1::new(),
+            BufferSizeInKB: 0,
+            u2: mfxInfoMFXUnion2::new(),
+            u3: mfxInfoMFXUnion3::new(),
+            NumSlice: 0,
+            NumRefFrame: 0,
+            EncodedOrder: 0,
+        }
+    }
+}
+
+// mfxInfoMFX with the JPEG encoding options in place of the encoding ones
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxInfoMFXJPEG {
+    pub reserved: [mfxU32; 7],
+    pub LowPower: mfxU16,
+    pub BRCParamMultiplier: mfxU16,
+    pub FrameInfo: mfxFrameInfo,
+    pub CodecId: mfxU32,
+    pub CodecProfile: mfxU16,
+    pub CodecLevel: mfxU16,
+    pub NumThread: mfxU16,
+
+    //  JPEG encoding options
+    pub Interleaved: mfxU16,
+    pub Quality: mfxU16,
+    pub RestartInterval: mfxU16,
+    pub reserved5: [mfxU16; 10],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtBuffer {
+    pub BufferId: mfxU32,
+    pub BufferSz: mfxU32,
+}
+
+impl mfxExtBuffer {
+    pub fn new() -> Self {
+        mfxExtBuffer {
+            BufferId: 0,
+            BufferSz: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxInfoVPP {
+    pub reserved: [mfxU32; 8],
+    pub In: mfxFrameInfo,
+    pub Out: mfxFrameInfo,
+}
+
+impl mfxInfoVPP {
+    pub fn new() -> Self {
+        mfxInfoVPP {
+            reserved: [0; 8],
+            In: mfxFrameInfo::new(),
+            Out: mfxFrameInfo::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxVideoParamUnion {
+    pub mfx: mfxInfoMFX,
+    pub jpeg: mfxInfoMFXJPEG,
+    pub vpp: mfxInfoVPP,
+}
+
+impl mfxVideoParamUnion {
+    pub fn new() -> Self {
+        mfxVideoParamUnion {
+            mfx: mfxInfoMFX::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxVideoParam {
+    pub AllocId: mfxU32,
+    pub reserved: [mfxU32; 2],
+    pub reserved3: mfxU16,
+    pub AsyncDepth: mfxU16,
+    pub u: mfxVideoParamUnion,
+    pub Protected: mfxU16,
+    pub IOPattern: mfxU16,
+    pub ExtParam: *const *const mfxExtBuffer,
+    pub NumExtParam: mfxU16,
+    pub reserved2: mfxU16,
+}
+
+impl mfxVideoParam {
+    pub fn new() -> Self {
+        mfxVideoParam {
+            AllocId: 0,
+            reserved: [0; 2],
+            reserved3: 0,
+            AsyncDepth: 0,
+            u: mfxVideoParamUnion::new(),
+            Protected: 0,
+            IOPattern: 0,
+            ExtParam: ptr::null(),
+            NumExtParam: 0,
+            reserved2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct mfxFrameAllocRequest {
+    pub AllocId: mfxU32,
+    pub reserved3: [mfxU32; 3],
+    pub Info: mfxFrameInfo,
+    pub Type: mfxU16,
+    pub NumFrameMin: mfxU16,
+    pub NumFrameSuggested: mfxU16,
+    pub reserved2: mfxU16,
+}
+
+impl mfxFrameAllocRequest {
+    pub fn new() -> Self {
+        mfxFrameAllocRequest {
+            AllocId: 0,
+            reserved3: [0; 3],
+            Info: mfxFrameInfo::new(),
+            Type: 0,
+            NumFrameMin: 0,
+            NumFrameSuggested: 0,
+            reserved2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct mfxFrameData {
+    // TODO: union ExtParam: const* const* mfxExtBuffer
+    pub reserved2: mfxU64,
+    pub NumExtParam: mfxU16,
+    pub reserved: [mfxU16; 9],
+    pub MemType: mfxU16,
+    pub PitchHigh: mfxU16,
+    pub TimeStamp: mfxU64,
+    pub FrameOrder: mfxU32,
+    pub Locked: mfxU16,
+    // TODO: union Pitch
+    pub PitchLow: mfxU16,
+
+    pub Y: *mut mfxU8,
+    // union
+    pub UV: *mut mfxU8,
+    // union
+    pub V: *mut mfxU8,
+    pub A: *mut mfxU8,
+    pub MemId: mfxMemId,
+    pub Corrupted: mfxU16,
+    pub DataFlag: mfxU16,
+}
+
+impl mfxFrameData {
+    pub fn new() -> Self {
+        mfxFrameData {
+            reserved2: 0,
+            NumExtParam: 0,
+            reserved: [0; 9],
+            MemType: 0,
+            PitchHigh: 0,
+            TimeStamp: 0,
+            FrameOrder: 0,
+            Locked: 0,
+            PitchLow: 0,
+
+            Y: ptr::null_mut(),
+            UV: ptr::null_mut(),
+            V: ptr::null_mut(),
+            A: ptr::null_mut(),
+            MemId: ptr::null(),
+            Corrupted: 0,
+            DataFlag: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct mfxFrameSurface1 {
+    pub reserved: [mfxU32; 4],
+    pub Info: mfxFrameInfo,
+    pub Data: mfxFrameData,
+}
+
+impl mfxFrameSurface1 {
+    pub fn new() -> Self {
+        mfxFrameSurface1 {
+            reserved: [0; 4],
+            Info: mfxFrameInfo::new(),
+            Data: mfxFrameData::new(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct mfxBitstream {
+    // TODO: union encrypted data
+    pub reserved: [mfxU32; 6],
+    pub DecodeTimeStamp: mfxI64,
+    pub TimeStamp: mfxU64,
+    pub Data: *const mfxU8,
+    pub DataOffset: mfxU32,
+    pub DataLength: mfxU32,
+    pub MaxLength: mfxU32,
+    pub PicStruct: mfxU16,
+    pub FrameType: mfxU16,
+    pub DataFlag: mfxU16,
+    pub reserved2: mfxU16,
+}
+
+impl mfxBitstream {
+    pub fn new() -> Self {
+        mfxBitstream {
+            reserved: [0; 6],
+            DecodeTimeStamp: 0,
+            TimeStamp: 0,
+            Data: ptr::null(),
+            DataOffset: 0,
+            DataLength: 0,
+            MaxLength: 0,
+            PicStruct: 0,
+            FrameType: 0,
+            DataFlag: 0,
+            reserved2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxEncodeCtrl {
+    pub Header: mfxExtBuffer,
+    pub reserved: [mfxU32; 5],
+    pub SkipFrame: mfxU16,
+
+    pub QP: mfxU16,
+    pub FrameType: mfxU16,
+    pub NumExtParam: mfxU16,
+    pub NumPayload: mfxU16,
+    pub reserved2: mfxU16,
+
+    pub ExtParam: *const *const mfxExtBuffer,
+    pub Payload: *const *const mfxPayload,
+}
+
+impl mfxEncodeCtrl {
+    pub fn new() -> Self {
+        // frame type, QP and skip left to the encoder
+        unsafe { mem::zeroed() }
+    }
+}
+
+#[repr(C)]
+pub struct mfxPayload {
+    pub reserved: [mfxU32; 4],
+    pub Data: *const mfxU8,
+    pub NumBit: mfxU32,
+    pub Type: mfxU16,
+    pub BufSize: mfxU16,
+}
+
+#[repr(C)]
+pub struct mfxExtVppAuxData {
+    Header: mfxExtBuffer,
+
+    // TODO: union
+    SpatialComplexity: mfxU32,
+    TemporalComplexity: mfxU32,
+
+    SceneChangeRate: mfxU16,
+    RepeatedFrame: mfxU16,
+}
+
+// Coding options
+
+pub const MFX_EXTBUFF_CODING_OPTION: mfxU32 = 0x504f4443;
+pub const MFX_EXTBUFF_CODING_OPTION2: mfxU32 = 0x324f4443;
+pub const MFX_EXTBUFF_CODING_OPTION3: mfxU32 = 0x334f4443;
+
+pub const MFX_TRELLIS_UNKNOWN: u16 = 0;
+pub const MFX_TRELLIS_OFF: u16 = 0x01;
+pub const MFX_TRELLIS_I: u16 = 0x02;
+pub const MFX_TRELLIS_P: u16 = 0x04;
+pub const MFX_TRELLIS_B: u16 = 0x08;
+
+pub const MFX_B_REF_UNKNOWN: u16 = 0;
+pub const MFX_B_REF_OFF: u16 = 1;
+pub const MFX_B_REF_PYRAMID: u16 = 2;
+
+pub const MFX_SKIPFRAME_NO_SKIP: u16 = 0;
+pub const MFX_SKIPFRAME_INSERT_DUMMY: u16 = 1;
+pub const MFX_SKIPFRAME_INSERT_NOTHING: u16 = 2;
+pub const MFX_SKIPFRAME_BRC_ONLY: u16 = 3;
+
+pub const MFX_WEIGHTED_PRED_UNKNOWN: u16 = 0;
+pub const MFX_WEIGHTED_PRED_DEFAULT: u16 = 1;
+pub const MFX_WEIGHTED_PRED_EXPLICIT: u16 = 2;
+pub const MFX_WEIGHTED_PRED_IMPLICIT: u16 = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxI16Pair {
+    pub x: mfxI16,
+    pub y: mfxI16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtCodingOption {
+    pub Header: mfxExtBuffer,
+    pub reserved1: mfxU16,
+    pub RateDistortionOpt: mfxU16,
+    pub MECostType: mfxU16,
+    pub MESearchType: mfxU16,
+    pub MVSearchWindow: mfxI16Pair,
+    pub EndOfSequence: mfxU16,
+    pub FramePicture: mfxU16,
+    pub CAVLC: mfxU16,
+    pub reserved2: [mfxU16; 2],
+    pub RecoveryPointSEI: mfxU16,
+    pub ViewOutput: mfxU16,
+    pub NalHrdConformance: mfxU16,
+    pub SingleSeiNalUnit: mfxU16,
+    pub VuiVclHrdParameters: mfxU16,
+    pub RefPicListReordering: mfxU16,
+    pub ResetRefList: mfxU16,
+    pub RefPicMarkRep: mfxU16,
+    pub FieldOutput: mfxU16,
+    pub IntraPredBlockSize: mfxU16,
+    pub InterPredBlockSize: mfxU16,
+    pub MVPrecision: mfxU16,
+    pub MaxDecFrameBuffering: mfxU16,
+    pub AUDelimiter: mfxU16,
+    pub EndOfStream: mfxU16,
+    pub PicTimingSEI: mfxU16,
+    pub VuiNalHrdParameters: mfxU16,
+}
+
+impl mfxExtCodingOption {
+    pub fn new() -> Self {
+        let mut option: mfxExtCodingOption = unsafe { mem::zeroed() };
+        option.Header = mfxExtBuffer {
+            BufferId: MFX_EXTBUFF_CODING_OPTION,
+            BufferSz: mem::size_of::<mfxExtCodingOption>() as mfxU32,
+        };
+        option
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtCodingOption2 {
+    pub Header: mfxExtBuffer,
+    pub IntRefType: mfxU16,
+    pub IntRefCycleSize: mfxU16,
+    pub IntRefQPDelta: mfxI16,
+    pub MaxFrameSize: mfxU32,
+    pub MaxSliceSize: mfxU32,
+    pub BitrateLimit: mfxU16,
+    pub MBBRC: mfxU16,
+    pub ExtBRC: mfxU16,
+    pub LookAheadDepth: mfxU16,
+    pub Trellis: mfxU16,
+    pub RepeatPPS: mfxU16,
+    pub BRefType: mfxU16,
+    pub AdaptiveI: mfxU16,
+    pub AdaptiveB: mfxU16,
+    pub LookAheadDS: mfxU16,
+    pub NumMbPerSlice: mfxU16,
+    pub SkipFrame: mfxU16,
+    pub MinQPI: mfxU8,
+    pub MaxQPI: mfxU8,
+    pub MinQPP: mfxU8,
+    pub MaxQPP: mfxU8,
+    pub MinQPB: mfxU8,
+    pub MaxQPB: mfxU8,
+    pub FixedFrameRate: mfxU16,
+    pub DisableDeblockingIdc: mfxU16,
+    pub DisableVUI: mfxU16,
+    pub BufferingPeriodSEI: mfxU16,
+    pub EnableMAD: mfxU16,
+    pub UseRawRef: mfxU16,
+}
+
+impl mfxExtCodingOption2 {
+    pub fn new() -> Self {
+        // every option but the header defaults to zero, "let the runtime decide"
+        let mut option: mfxExtCodingOption2 = unsafe { mem::zeroed() };
+        option.Header = mfxExtBuffer {
+            BufferId: MFX_EXTBUFF_CODING_OPTION2,
+            BufferSz: mem::size_of::<mfxExtCodingOption2>() as mfxU32,
+        };
+        option
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtCodingOption3 {
+    pub Header: mfxExtBuffer,
+    pub NumSliceI: mfxU16,
+    pub NumSliceP: mfxU16,
+    pub NumSliceB: mfxU16,
+    pub WinBRCMaxAvgKbps: mfxU16,
+    pub WinBRCSize: mfxU16,
+    pub QVBRQuality: mfxU16,
+    pub EnableMBQP: mfxU16,
+    pub IntRefCycleDist: mfxU16,
+    pub DirectBiasAdjustment: mfxU16,
+    pub GlobalMotionBiasAdjustment: mfxU16,
+    pub MVCostScalingFactor: mfxU16,
+    pub MBDisableSkipMap: mfxU16,
+    pub WeightedPred: mfxU16,
+    pub WeightedBiPred: mfxU16,
+    pub AspectRatioInfoPresent: mfxU16,
+    pub OverscanInfoPresent: mfxU16,
+    pub OverscanAppropriate: mfxU16,
+    pub TimingInfoPresent: mfxU16,
+    pub BitstreamRestriction: mfxU16,
+    pub LowDelayHrd: mfxU16,
+    pub MotionVectorsOverPicBoundaries: mfxU16,
+    pub reserved1: [mfxU16; 2],
+    pub ScenarioInfo: mfxU16,
+    pub ContentInfo: mfxU16,
+    pub PRefType: mfxU16,
+    pub FadeDetection: mfxU16,
+    pub reserved2: [mfxU16; 2],
+    pub GPB: mfxU16,
+    pub MaxFrameSizeI: mfxU32,
+    pub MaxFrameSizeP: mfxU32,
+    pub reserved3: [mfxU32; 3],
+    pub EnableQPOffset: mfxU16,
+    pub QPOffset: [mfxI16; 8],
+    pub NumRefActiveP: [mfxU16; 8],
+    pub NumRefActiveBL0: [mfxU16; 8],
+    pub NumRefActiveBL1: [mfxU16; 8],
+    pub reserved6: mfxU16,
+    pub TransformSkip: mfxU16,
+    pub TargetChromaFormatPlus1: mfxU16,
+    pub TargetBitDepthLuma: mfxU16,
+    pub TargetBitDepthChroma: mfxU16,
+    pub BRCPanicMode: mfxU16,
+    pub LowDelayBRC: mfxU16,
+    pub EnableMBForceIntra: mfxU16,
+    pub AdaptiveMaxFrameSize: mfxU16,
+    pub RepartitionCheckEnable: mfxU16,
+    pub reserved5: [mfxU16; 3],
+    pub EncodedUnitsInfo: mfxU16,
+    pub EnableNalUnitType: mfxU16,
+    pub ExtBrcAdaptiveLTR: mfxU16,
+    pub reserved: [mfxU16; 163],
+}
+
+impl mfxExtCodingOption3 {
+    pub fn new() -> Self {
+        let mut option: mfxExtCodingOption3 = unsafe { mem::zeroed() };
+        option.Header = mfxExtBuffer {
+            BufferId: MFX_EXTBUFF_CODING_OPTION3,
+            BufferSz: mem::size_of::<mfxExtCodingOption3>() as mfxU32,
+        };
+        option
+    }
+}
+
+// Encoder reset
+
+pub const MFX_EXTBUFF_ENCODER_RESET_OPTION: mfxU32 = 0x54455352;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtEncoderResetOption {
+    pub Header: mfxExtBuffer,
+    pub StartNewSequence: mfxU16,
+    pub reserved: [mfxU16; 11],
+}
+
+impl mfxExtEncoderResetOption {
+    pub fn new() -> Self {
+        let mut option: mfxExtEncoderResetOption = unsafe { mem::zeroed() };
+        option.Header = mfxExtBuffer {
+            BufferId: MFX_EXTBUFF_ENCODER_RESET_OPTION,
+            BufferSz: mem::size_of::<mfxExtEncoderResetOption>() as mfxU32,
+        };
+        option
+    }
+}
+
+// Codec extension buffers
+
+pub const MFX_EXTBUFF_AV1_TILE_PARAM: mfxU32 = 0x4c543141;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtAV1TileParam {
+    pub Header: mfxExtBuffer,
+    pub NumTileRows: mfxU16,
+    pub NumTileColumns: mfxU16,
+    pub NumTileGroups: mfxU16,
+    pub reserved: [mfxU16; 5],
+}
+
+impl mfxExtAV1TileParam {
+    pub fn new() -> Self {
+        mfxExtAV1TileParam {
+            Header: mfxExtBuffer {
+                BufferId: MFX_EXTBUFF_AV1_TILE_PARAM,
+                BufferSz: mem::size_of::<mfxExtAV1TileParam>() as mfxU32,
+            },
+            NumTileRows: 0,
+            NumTileColumns: 0,
+            NumTileGroups: 0,
+            reserved: [0; 5],
+        }
+    }
+}
+
+pub const MFX_EXTBUFF_VP9_PARAM: mfxU32 = 0x52415039;
+pub const MFX_EXTBUFF_VP9_SEGMENTATION: mfxU32 = 0x47455339;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtVP9Param {
+    pub Header: mfxExtBuffer,
+    pub FrameWidth: mfxU16,
+    pub FrameHeight: mfxU16,
+    pub WriteIVFHeaders: mfxU16,
+    pub reserved1: [mfxI16; 6],
+    pub QIndexDeltaLumaDC: mfxI16,
+    pub QIndexDeltaChromaAC: mfxI16,
+    pub QIndexDeltaChromaDC: mfxI16,
+    pub NumTileRows: mfxU16,
+    pub NumTileColumns: mfxU16,
+    pub reserved: [mfxU16; 110],
+}
+
+impl mfxExtVP9Param {
+    pub fn new() -> Self {
+        mfxExtVP9Param {
+            Header: mfxExtBuffer {
+                BufferId: MFX_EXTBUFF_VP9_PARAM,
+                BufferSz: mem::size_of::<mfxExtVP9Param>() as mfxU32,
+            },
+            FrameWidth: 0,
+            FrameHeight: 0,
+            WriteIVFHeaders: 0,
+            reserved1: [0; 6],
+            QIndexDeltaLumaDC: 0,
+            QIndexDeltaChromaAC: 0,
+            QIndexDeltaChromaDC: 0,
+            NumTileRows: 0,
+            NumTileColumns: 0,
+            reserved: [0; 110],
+        }
+    }
+}
+
+pub const MFX_VP9_SEGMENT_FEATURE_QINDEX: u16 = 0x0001;
+pub const MFX_VP9_SEGMENT_FEATURE_LOOP_FILTER: u16 = 0x0002;
+pub const MFX_VP9_SEGMENT_FEATURE_REFERENCE: u16 = 0x0004;
+pub const MFX_VP9_SEGMENT_FEATURE_SKIP: u16 = 0x0008;
+
+pub const MFX_VP9_SEGMENT_ID_BLOCK_SIZE_UNKNOWN: u16 = 0;
+pub const MFX_VP9_SEGMENT_ID_BLOCK_SIZE_8x8: u16 = 8;
+pub const MFX_VP9_SEGMENT_ID_BLOCK_SIZE_16x16: u16 = 16;
+pub const MFX_VP9_SEGMENT_ID_BLOCK_SIZE_32x32: u16 = 32;
+pub const MFX_VP9_SEGMENT_ID_BLOCK_SIZE_64x64: u16 = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxVP9SegmentParam {
+    pub FeatureEnabled: mfxU16,
+    pub QIndexDelta: mfxI16,
+    pub LoopFilterLevelDelta: mfxI16,
+    pub ReferenceFrame: mfxU16,
+    pub reserved: [mfxU16; 12],
+}
+
+impl mfxVP9SegmentParam {
+    pub fn new() -> Self {
+        mfxVP9SegmentParam {
+            FeatureEnabled: 0,
+            QIndexDelta: 0,
+            LoopFilterLevelDelta: 0,
+            ReferenceFrame: 0,
+            reserved: [0; 12],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxExtVP9Segmentation {
+    pub Header: mfxExtBuffer,
+    pub NumSegments: mfxU16,
+    pub Segment: [mfxVP9SegmentParam; 8],
+    pub SegmentIdBlockSize: mfxU16,
+    pub NumSegmentIdAlloc: mfxU32,
+    pub SegmentId: *mut mfxU8,
+    pub reserved: [mfxU16; 52],
+}
+
+impl mfxExtVP9Segmentation {
+    pub fn new() -> Self {
+        mfxExtVP9Segmentation {
+            Header: mfxExtBuffer {
+                BufferId: MFX_EXTBUFF_VP9_SEGMENTATION,
+                BufferSz: mem::size_of::<mfxExtVP9Segmentation>() as mfxU32,
+            },
+            NumSegments: 0,
+            Segment: [mfxVP9SegmentParam::new(); 8],
+            SegmentIdBlockSize: 0,
+            NumSegmentIdAlloc: 0,
+            SegmentId: ptr::null_mut(),
+            reserved: [0; 52],
+        }
+    }
+}
+
+// Media SDK 1.x plugins
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct mfxPluginUID {
+    pub Data: [mfxU8; 16],
+}
+
+pub const MFX_PLUGINID_HEVCE_HW: mfxPluginUID = mfxPluginUID {
+    Data: [
+        0x6f, 0xad, 0xc7, 0x91, 0xa0, 0xc2, 0xeb, 0x47, 0x9a, 0xb6, 0xdc, 0xd5, 0xea, 0x9d, 0xa3,
+        0x47,
+    ],
+};
+
+// oneVPL dispatcher
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxStructVersion {
+    pub Minor: mfxU8,
+    pub Major: mfxU8,
+}
+
+impl mfxStructVersion {
+    pub const fn new(Major: mfxU8, Minor: mfxU8) -> Self {
+        mfxStructVersion { Major, Minor }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union mfxVariantData {
+    pub U8: mfxU8,
+    pub I8: i8,
+    pub U16: mfxU16,
+    pub I16: i16,
+    pub U32: mfxU32,
+    pub I32: mfxI32,
+    pub U64: mfxU64,
+    pub I64: mfxI64,
+    pub F32: f32,
+    pub F64: f64,
+    pub Ptr: mfxHDL,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxVariant {
+    pub Version: mfxStructVersion,
+    pub Type: mfxVariantType,
+    pub Data: mfxVariantData,
+}
+
+impl mfxVariant {
+    pub fn u32(value: mfxU32) -> Self {
+        mfxVariant {
+            Version: mfxStructVersion::new(1, 0),
+            Type: MFX_VARIANT_TYPE_U32,
+            Data: mfxVariantData { U32: value },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct mfxRange32U {
+    pub Min: mfxU32,
+    pub Max: mfxU32,
+    pub Step: mfxU32,
+}
+
+#[repr(C)]
+pub struct mfxDeviceDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 6],
+    pub MediaAdapterType: mfxU16,
+    pub DeviceID: [mfxChar; MFX_STRFIELD_LEN],
+    pub NumSubDevices: mfxU16,
+    pub SubDevices: *mut libc::c_void,
+}
+
+#[repr(C)]
+pub struct mfxDecoderDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 7],
+    pub NumCodecs: mfxU16,
+    pub Codecs: *mut libc::c_void,
+}
+
+#[repr(C)]
+pub struct mfxEncoderMemDescription {
+    pub MemHandleType: mfxResourceType,
+    pub Width: mfxRange32U,
+    pub Height: mfxRange32U,
+    pub reserved: [mfxU16; 7],
+    pub NumColorFormats: mfxU16,
+    pub ColorFormats: *mut mfxU32,
+}
+
+#[repr(C)]
+pub struct mfxEncoderProfileDescription {
+    pub Profile: mfxU32,
+    pub reserved: [mfxU16; 7],
+    pub NumMemTypes: mfxU16,
+    pub MemDesc: *mut mfxEncoderMemDescription,
+}
+
+#[repr(C)]
+pub struct mfxEncoderCodecDescription {
+    pub CodecID: mfxU32,
+    pub MaxcodecLevel: mfxU16,
+    pub BiDirectionalPrediction: mfxU16,
+    pub reserved: [mfxU16; 7],
+    pub NumProfiles: mfxU16,
+    pub Profiles: *mut mfxEncoderProfileDescription,
+}
+
+#[repr(C)]
+pub struct mfxEncoderDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 7],
+    pub NumCodecs: mfxU16,
+    pub Codecs: *mut mfxEncoderCodecDescription,
+}
+
+#[repr(C)]
+pub struct mfxVPPDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 7],
+    pub NumFilters: mfxU16,
+    pub Filters: *mut libc::c_void,
+}
+
+#[repr(C)]
+pub struct mfxAccelerationModeDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 2],
+    pub NumAccelerationModes: mfxU16,
+    pub Mode: *mut mfxAccelerationMode,
+}
+
+#[repr(C)]
+pub struct mfxPoolPolicyDescription {
+    pub Version: mfxStructVersion,
+    pub reserved: [mfxU16; 7],
+    pub NumPoolPolicies: mfxU16,
+    pub Policy: *mut mfxU32,
+}
+
+#[repr(C)]
+pub struct mfxImplDescription {
+    pub Version: mfxStructVersion,
+    pub Impl: mfxImplType,
+    pub AccelerationMode: mfxAccelerationMode,
+    pub ApiVersion: mfxVersion,
+    pub ImplName: [mfxChar; MFX_IMPL_NAME_LEN],
+    pub License: [mfxChar; MFX_STRFIELD_LEN],
+    pub Keywords: [mfxChar; MFX_STRFIELD_LEN],
+    pub VendorID: mfxU32,
+    pub VendorImplID: mfxU32,
+    pub Dev: mfxDeviceDescription,
+    pub Dec: mfxDecoderDescription,
+    pub Enc: mfxEncoderDescription,
+    pub VPP: mfxVPPDescription,
+    // TODO: union with reserved3[4]
+    pub AccelerationModeDescription: mfxAccelerationModeDescription,
+    pub PoolPolicies: mfxPoolPolicyDescription,
+    pub reserved: [mfxU32; 8],
+    pub NumExtParam: mfxU32,
+    // TODO: union with Reserved2
+    pub ExtParam: *mut *mut mfxExtBuffer,
+}
+
+impl mfxImplDescription {
+    pub fn new() -> Self {
+        // integers, character arrays and null pointers only
+        unsafe { mem::zeroed() }
+    }
+}
+
+macro_rules! functions {
+    (
+        required { $($name:ident: fn($($arg:ident: $ty:ty),* $(,)*) -> $ret:ty;)* }
+        optional { $($oname:ident: fn($($oarg:ident: $oty:ty),* $(,)*) -> $oret:ty;)* }
+    ) => {
+        /// Media SDK entry points resolved from the runtime library.
+        ///
+        /// The `system` ABI is `stdcall` on 32-bit Windows and the C calling
+        /// convention everywhere else. Optional entry points are `None` when
+        /// the runtime does not export them, e.g. the oneVPL dispatcher API
+        /// in a Media SDK 1.x `libmfx`.
+        pub struct mfxFunctions {
+            $(pub $name: unsafe extern "system" fn($($arg: $ty),*) -> $ret,)*
+            $(pub $oname: Option<unsafe extern "system" fn($($oarg: $oty),*) -> $oret>,)*
+        }
+
+        impl mfxFunctions {
+            /// Resolves every entry point through `resolve`, which is given
+            /// the NUL-terminated symbol name. Fails with the name of the
+            /// first required symbol that cannot be found.
+            ///
+            /// # Safety
+            ///
+            /// Every address returned by `resolve` must be the entry point of
+            /// that name, and must stay mapped while the table is in use.
+            pub unsafe fn load<F>(mut resolve: F) -> Result<mfxFunctions, &'static str>
+            where
+                F: FnMut(&'static str) -> Option<*mut libc::c_void>,
+            {
+                Ok(mfxFunctions {
+                    $($name: match resolve(concat!(stringify!($name), "\0")) {
+                        Some(f) => std::mem::transmute::<
+                            *mut libc::c_void,
+                            unsafe extern "system" fn($($ty),*) -> $ret,
+                        >(f),
+                        None => return Err(stringify!($name)),
+                    },)*
+                    $($oname: resolve(concat!(stringify!($oname), "\0")).map(|f| {
+                        std::mem::transmute::<
+                            *mut libc::c_void,
+                            unsafe extern "system" fn($($oty),*) -> $oret,
+                        >(f)
+                    }),)*
+                })
+            }
+        }
+    };
+}
+
+functions! {
+    required {
+        MFXInit: fn(
+            implementation: mfxIMPL,
+            ver: *const mfxVersion,
+            session: *mut *mut mfxSession,
+        ) -> mfxStatus;
+
+        MFXClose: fn(session: *mut mfxSession) -> mfxStatus;
+
+        MFXQueryIMPL: fn(
+            session: *const mfxSession,
+            implementation: *mut mfxIMPL,
+        ) -> mfxStatus;
+
+        MFXQueryVersion: fn(session: *const mfxSession, version: *mut mfxVersion) -> mfxStatus;
+
+        MFXVideoENCODE_Query: fn(
+            session: *const mfxSession,
+            input: *const mfxVideoParam,
+            output: *mut mfxVideoParam,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_QueryIOSurf: fn(
+            session: *const mfxSession,
+            par: *const mfxVideoParam,
+            request: *mut mfxFrameAllocRequest,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_Init: fn(
+            session: *const mfxSession,
+            par: *const mfxVideoParam,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_GetVideoParam: fn(
+            session: *const mfxSession,
+            par: *mut mfxVideoParam,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_EncodeFrameAsync: fn(
+            session: *const mfxSession,
+            ctrl: *const mfxEncodeCtrl,
+            surface: *const mfxFrameSurface1,
+            bs: *mut mfxBitstream,
+            syncp: *mut mfxSyncPoint,
+        ) -> mfxStatus;
+
+        MFXVideoCORE_SyncOperation: fn(
+            session: *const mfxSession,
+            syncp: mfxSyncPoint,
+            wait: mfxU32,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_Reset: fn(
+            session: *const mfxSession,
+            par: *const mfxVideoParam,
+        ) -> mfxStatus;
+
+        MFXVideoENCODE_Close: fn(session: *const mfxSession) -> mfxStatus;
+
+        // VPP
+        MFXVideoVPP_QueryIOSurf: fn(
+            session: *const mfxSession,
+            par: *const mfxVideoParam,
+            request: *mut mfxFrameAllocRequest,
+        ) -> mfxStatus;
+
+        MFXVideoVPP_Init: fn(session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus;
+
+        MFXVideoVPP_RunFrameVPPAsync: fn(
+            session: *const mfxSession,
+            input: *const mfxFrameSurface1,
+            output: *mut mfxFrameSurface1,
+            aux: *const mfxExtVppAuxData,
+            syncp: *mut mfxSyncPoint,
+        ) -> mfxStatus;
+
+        MFXVideoVPP_Reset: fn(session: *const mfxSession, par: *mut mfxVideoParam) -> mfxStatus;
+
+        MFXVideoVPP_Close: fn(session: *const mfxSession) -> mfxStatus;
+    }
+
+    optional {
+        // Media SDK 1.x plugins, removed in oneVPL 2.x
+        MFXVideoUSER_Load: fn(
+            session: *const mfxSession,
+            uid: *const mfxPluginUID,
+            version: mfxU32,
+        ) -> mfxStatus;
+
+        MFXVideoUSER_UnLoad: fn(
+            session: *const mfxSession,
+            uid: *const mfxPluginUID,
+        ) -> mfxStatus;
+
+        // oneVPL 2.x dispatcher
+        MFXLoad: fn() -> mfxLoader;
+
+        MFXUnload: fn(loader: mfxLoader) -> ();
+
+        MFXCreateConfig: fn(loader: mfxLoader) -> mfxConfig;
+
+        MFXSetConfigFilterProperty: fn(
+            config: mfxConfig,
+            name: *const mfxU8,
+            value: mfxVariant,
+        ) -> mfxStatus;
+
+        MFXEnumImplementations: fn(
+            loader: mfxLoader,
+            i: mfxU32,
+            format: mfxImplCapsDeliveryFormat,
+            idesc: *mut mfxHDL,
+        ) -> mfxStatus;
+
+        MFXCreateSession: fn(
+            loader: mfxLoader,
+            i: mfxU32,
+            session: *mut *mut mfxSession,
+        ) -> mfxStatus;
+
+        MFXDispReleaseImplDescription: fn(loader: mfxLoader, hdl: mfxHDL) -> mfxStatus;
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, ErrorKind, Write};
+
+use crate::encoder::{Codec, EncoderConfig, Packet};
+use crate::sys::{MFX_FRAMETYPE_I, MFX_FRAMETYPE_IDR};
+
+const EBML: u32 = 0x1a45_dfa3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42f7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_a966;
+const TIMECODE_SCALE: u32 = 0x2a_d7b1;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const DEFAULT_DURATION: u32 = 0x23_e383;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const CLUSTER: u32 = 0x1f43_b675;
+const TIMECODE: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+
+// size of an element running to the end of the file
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+const TRACK_TYPE_VIDEO: u64 = 1;
+// millisecond timecodes
+const NANOSECONDS_PER_TICK: u64 = 1_000_000;
+const TICKS_PER_90KHZ: u64 = 90;
+
+/// Writes VP9 packets to a WebM file, playable by browsers.
+///
+/// The segment is written with an unknown size, as live streams are, so the
+/// writer only needs [`Write`]. Each cluster is buffered until the next key
+/// frame, or until block timecodes would overflow their 16 bits.
+pub struct WebmWriter<W: Write> {
+    writer: W,
+    // timecode and body of the cluster being filled
+    cluster: Option<(u64, Vec<u8>)>,
+}
+
+fn element_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+// Variable length size, on as few bytes as possible.
+fn element_size(out: &mut Vec<u8>, size: usize) {
+    let size = size as u64;
+    let length = (1..8).find(|&n| size < (1 << (7 * n)) - 1).unwrap_or(8);
+    let marked = size | 1 << (7 * length);
+    out.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    element_id(out, id);
+    element_size(out, body.len());
+    out.extend_from_slice(body);
+}
+
+fn uint_element(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+impl<W: Write> WebmWriter<W> {
+    /// Writes the EBML header, segment info and track for the stream
+    /// `config` describes.
+    pub fn new(mut writer: W, config: &EncoderConfig) -> io::Result<WebmWriter<W>> {
+        if config.codec != Codec::Vp9 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "WebM output is only supported for VP9",
+            ));
+        }
+
+        let mut header = Vec::new();
+        let mut ebml = Vec::new();
+        uint_element(&mut ebml, EBML_VERSION, 1);
+        uint_element(&mut ebml, EBML_READ_VERSION, 1);
+        uint_element(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut ebml, DOC_TYPE, b"webm");
+        uint_element(&mut ebml, DOC_TYPE_VERSION, 4);
+        uint_element(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut header, EBML, &ebml);
+
+        element_id(&mut header, SEGMENT);
+        header.extend_from_slice(&UNKNOWN_SIZE);
+
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMECODE_SCALE, NANOSECONDS_PER_TICK);
+        element(&mut info, MUXING_APP, b"qsv-rust");
+        element(&mut info, WRITING_APP, b"qsv-rust");
+        element(&mut header, INFO, &info);
+
+        let mut video = Vec::new();
+        uint_element(&mut video, PIXEL_WIDTH, config.width as u64);
+        uint_element(&mut video, PIXEL_HEIGHT, config.height as u64);
+        let frame_duration =
+            1_000_000_000 * config.frame_rate.den as u64 / config.frame_rate.num as u64;
+        let mut track = Vec::new();
+        uint_element(&mut track, TRACK_NUMBER, 1);
+        uint_element(&mut track, TRACK_UID, 1);
+        uint_element(&mut track, TRACK_TYPE, TRACK_TYPE_VIDEO);
+        element(&mut track, CODEC_ID, b"V_VP9");
+        uint_element(&mut track, DEFAULT_DURATION, frame_duration);
+        element(&mut track, VIDEO, &video);
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &track);
+        element(&mut header, TRACKS, &tracks);
+
+        writer.write_all(&header)?;
+        Ok(WebmWriter {
+            writer,
+            cluster: None,
+        })
+    }
+
+    /// Appends `packet` as a block of the video track.
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        let timecode = packet.timestamp / TICKS_PER_90KHZ;
+        let key_frame = packet.frame_type & (MFX_FRAMETYPE_I | MFX_FRAMETYPE_IDR) != 0;
+        let fits = match self.cluster {
+            Some((start, _)) => timecode >= start && timecode - start <= i16::MAX as u64,
+            None => false,
+        };
+        if key_frame || !fits {
+            self.flush_cluster()?;
+            let mut body = Vec::new();
+            uint_element(&mut body, TIMECODE, timecode);
+            self.cluster = Some((timecode, body));
+        }
+
+        let (start, body) = self.cluster.as_mut().unwrap();
+        let mut block = Vec::with_capacity(packet.data.len() + 4);
+        // track number 1, relative timecode, flags
+        block.push(0x81);
+        block.extend_from_slice(&((timecode - *start) as i16).to_be_bytes());
+        block.push(if key_frame { 0x80 } else { 0x00 });
+        block.extend_from_slice(&packet.data);
+        element(body, SIMPLE_BLOCK, &block);
+        Ok(())
+    }
+
+    /// Writes the last cluster and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_cluster()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if let Some((_, body)) = self.cluster.take() {
+            let mut cluster = Vec::with_capacity(body.len() + 12);
+            element(&mut cluster, CLUSTER, &body);
+            self.writer.write_all(&cluster)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+extern crate qsv_rust;
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use qsv_rust::{Codec, Encoder, EncoderConfig, IvfWriter, MockBackend};
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[test]
+fn av1_stream_is_written_as_ivf() {
+    let mut config = EncoderConfig::new(64, 48, 500);
+    config.codec = Codec::Av1;
+    config.frame_rate = "30000/1001".parse().unwrap();
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let mut writer = IvfWriter::new(Cursor::new(Vec::new()), &config).unwrap();
+    let frame = vec![0x80; encoder.frame_size()];
+    let mut packets = Vec::new();
+    for _ in 0..3 {
+        packets.extend(encoder.encode(&frame).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    for packet in &packets {
+        writer.write_packet(packet).unwrap();
+    }
+    let file = writer.finish().unwrap().into_inner();
+
+    assert_eq!(&file[..4], b"DKIF");
+    assert_eq!(u16_at(&file, 6), 32);
+    assert_eq!(&file[8..12], b"AV01");
+    assert_eq!((u16_at(&file, 12), u16_at(&file, 14)), (64, 48));
+    assert_eq!((u32_at(&file, 16), u32_at(&file, 20)), (30000, 1001));
+    assert_eq!(u32_at(&file, 24), 3);
+
+    // each frame header is the size and the frame number
+    let mut offset = 32;
+    for (n, packet) in packets.iter().enumerate() {
+        let size = u32_at(&file, offset) as usize;
+        assert_eq!(size, packet.data.len());
+        assert_eq!(u64_at(&file, offset + 4), n as u64);
+        assert_eq!(&file[offset + 12..offset + 12 + size], &packet.data[..]);
+        offset += 12 + size;
+    }
+    assert_eq!(offset, file.len());
+}
@@ -0,0 +1,1217 @@
+extern crate qsv_rust;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use qsv_rust::sys::{MFX_FRAMETYPE_I, MFX_FRAMETYPE_IDR};
+use qsv_rust::{
+    query_capabilities_with_backend, BitrateParams, Codec, Encoder, EncoderConfig, Error,
+    ForcedFrame, FrameControl, FrameRate, InputFormat, KeyframeInterval, Level, MfxError,
+    MockBackend, Packet, Profile, RateControl, SkipFrame, Trellis, Vp9Segment, Vp9Segmentation,
+    WeightedPrediction,
+};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+fn config() -> EncoderConfig {
+    EncoderConfig::new(WIDTH, HEIGHT, 500)
+}
+
+fn frame(n: usize) -> Vec<u8> {
+    (0..WIDTH * HEIGHT * 3 / 2).map(|i| (i + n) as u8).collect()
+}
+
+// Payload of the first NAL unit, without emulation prevention bytes.
+fn first_nal_payload(au: &[u8]) -> Vec<u8> {
+    let end = au[4..]
+        .windows(3)
+        .position(|w| w == [0, 0, 1])
+        .map_or(au.len(), |i| i + 4 - 1);
+    let mut payload = Vec::new();
+    let mut zeros = 0;
+    for &b in &au[5..end] {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        payload.push(b);
+    }
+    payload
+}
+
+fn encode_all(encoder: &mut Encoder, frames: usize) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    for n in 0..frames {
+        packets.extend(encoder.encode(&frame(n)).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    packets
+}
+
+#[test]
+fn every_frame_is_encoded() {
+    let backend = Arc::new(MockBackend::with_encode_delay(3));
+    let mut encoder = Encoder::with_backend(&config(), backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 10);
+    assert_eq!(packets.len(), 10);
+}
+
+#[test]
+fn async_depth_keeps_frames_in_flight() {
+    let backend = Arc::new(MockBackend::with_encode_delay(0));
+    let mut config = config();
+    config.async_depth = 3;
+    let mut encoder = Encoder::with_backend(&config, backend.clone()).unwrap();
+
+    // nothing is waited for until three frames are in flight
+    for n in 0..2 {
+        assert!(encoder.encode(&frame(n)).unwrap().is_empty());
+        assert_eq!(backend.operations_in_flight(), n + 1);
+    }
+    let mut packets = Vec::new();
+    for n in 2..10 {
+        let synced = encoder.encode(&frame(n)).unwrap();
+        assert_eq!(synced.len(), 1);
+        assert_eq!(backend.operations_in_flight(), 2);
+        packets.extend(synced);
+    }
+    packets.extend(encoder.flush().unwrap());
+    assert_eq!(backend.operations_in_flight(), 0);
+
+    let timestamps: Vec<u64> = packets.iter().map(|p| p.timestamp).collect();
+    let expected: Vec<u64> = (0..10).map(|n| n * 3000).collect();
+    assert_eq!(timestamps, expected);
+}
+
+#[test]
+fn surface_pools_keep_up_with_the_frames_in_flight() {
+    for delay in 0..4 {
+        for depth in 1..6 {
+            let mut config = config();
+            config.async_depth = depth;
+            let backend = Arc::new(MockBackend::with_encode_delay(delay));
+            let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+            let packets = encode_all(&mut encoder, 20);
+            assert_eq!(packets.len(), 20, "delay {}, async depth {}", delay, depth);
+        }
+    }
+}
+
+#[test]
+fn vpp_output_is_encoded_in_place() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.async_depth = 2;
+    let mut encoder = Encoder::with_backend(&config, backend.clone()).unwrap();
+
+    let packets = encode_all(&mut encoder, 10);
+    assert_eq!(packets.len(), 10);
+    // every frame went to ENCODE in the surface VPP wrote it to
+    assert_eq!(backend.encoded_vpp_outputs(), 10);
+
+    // a reconfigured pipeline still fits the shared surfaces
+    config.bitrate = 1000;
+    encoder.reconfigure(&config, false).unwrap();
+    encode_all(&mut encoder, 5);
+    assert_eq!(backend.encoded_vpp_outputs(), 15);
+}
+
+#[test]
+fn frames_in_flight_are_drained() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.async_depth = 4;
+    let mut encoder = Encoder::with_backend(&config, backend.clone()).unwrap();
+
+    for n in 0..5 {
+        assert!(encoder.encode(&frame(n)).unwrap().is_empty());
+    }
+    assert_eq!(encoder.flush().unwrap().len(), 5);
+    assert_eq!(backend.operations_in_flight(), 0);
+
+    let mut jpeg = jpeg_config(80);
+    jpeg.async_depth = 4;
+    let mut encoder = Encoder::with_backend(&jpeg, backend.clone()).unwrap();
+    for n in 0..3 {
+        let image = encoder.encode_image(&frame(n)).unwrap();
+        assert_eq!(image.timestamp, n as u64 * 3000);
+        assert_eq!(backend.operations_in_flight(), 0);
+    }
+}
+
+#[test]
+fn packets_are_annex_b() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config(), backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    for packet in &packets {
+        assert_eq!(&packet.data[..4], &[0, 0, 0, 1]);
+    }
+    // SPS leads the first access unit, later ones start with a non-IDR slice
+    assert_eq!(packets[0].data[4] & 0x1f, 7);
+    assert_eq!(packets[1].data[4] & 0x1f, 1);
+}
+
+#[test]
+fn frame_rate_sets_timestamps_and_vui_timing() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.frame_rate = "30000/1001".parse().unwrap();
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 4);
+    let timestamps: Vec<u64> = packets.iter().map(|p| p.timestamp).collect();
+    assert_eq!(timestamps, [0, 3003, 6006, 9009]);
+
+    let sps = first_nal_payload(&packets[0].data);
+    assert_eq!(&sps[7..11], &1001u32.to_be_bytes());
+    assert_eq!(&sps[11..15], &60000u32.to_be_bytes());
+}
+
+#[test]
+fn dropping_encoder_closes_session() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config(), backend.clone()).unwrap();
+    encoder.encode(&frame(0)).unwrap();
+    assert_eq!(backend.open_sessions(), 1);
+
+    drop(encoder);
+    assert_eq!(backend.open_sessions(), 0);
+}
+
+#[test]
+fn session_is_created_through_the_dispatcher() {
+    let backend = Arc::new(MockBackend::new());
+    let encoder = Encoder::with_backend(&config(), backend.clone()).unwrap();
+    assert_eq!(backend.open_loaders(), 1);
+
+    drop(encoder);
+    assert_eq!(backend.open_loaders(), 0);
+}
+
+#[test]
+fn legacy_runtime_falls_back_to_mfx_init() {
+    let backend = Arc::new(MockBackend::new().legacy());
+    let mut encoder = Encoder::with_backend(&config(), backend.clone()).unwrap();
+    assert_eq!(backend.open_loaders(), 0);
+
+    let packets = encode_all(&mut encoder, 4);
+    assert_eq!(packets.len(), 4);
+}
+
+#[test]
+fn failed_init_closes_session() {
+    let backend = Arc::new(MockBackend::new());
+    // accepted by VPP, too large for the mock encoder
+    let mut config = config();
+    config.width = 8192;
+
+    assert!(Encoder::with_backend(&config, backend.clone()).is_err());
+    assert_eq!(backend.open_sessions(), 0);
+    assert_eq!(backend.open_loaders(), 0);
+}
+
+#[test]
+fn invalid_config_is_rejected_before_opening_a_session() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.height = 47;
+
+    match Encoder::with_backend(&config, backend.clone()) {
+        Err(Error::Config(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("odd height accepted"),
+    }
+    assert_eq!(backend.open_loaders(), 0);
+}
+
+#[test]
+fn nv12_input_is_encoded() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.input_format = InputFormat::Nv12;
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    assert_eq!(packets.len(), 3);
+}
+
+fn hevc_config(profile: Option<Profile>) -> EncoderConfig {
+    let mut config = config();
+    config.codec = Codec::Hevc;
+    config.profile = profile;
+    config
+}
+
+#[test]
+fn hevc_packets_start_with_a_vps() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&hevc_config(None), backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    assert_eq!(packets.len(), 3);
+    assert_eq!(&packets[0].data[..4], &[0, 0, 0, 1]);
+    // VPS, then TRAIL_R slices
+    assert_eq!((packets[0].data[4] >> 1) & 0x3f, 32);
+    assert_eq!((packets[1].data[4] >> 1) & 0x3f, 1);
+}
+
+#[test]
+fn legacy_runtime_loads_the_hevc_plugin() {
+    let backend = Arc::new(MockBackend::new().legacy());
+    let mut encoder = Encoder::with_backend(&hevc_config(None), backend.clone()).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    assert_eq!(packets.len(), 3);
+    drop(encoder);
+    assert_eq!(backend.open_sessions(), 0);
+}
+
+#[test]
+fn hevc_profiles_encode_the_same_picture() {
+    let encode = |profile| {
+        let backend = Arc::new(MockBackend::new());
+        let mut encoder = Encoder::with_backend(&hevc_config(profile), backend).unwrap();
+        encode_all(&mut encoder, 3)
+    };
+    let main = encode(Some(Profile::Main));
+    for profile in [Profile::Main10, Profile::Main444] {
+        let packets = encode(Some(profile));
+        // same slices, the luma checksum is taken on the 8 most significant bits
+        for (a, b) in main.iter().zip(&packets) {
+            assert_eq!(a.data[a.data.len() - 8..], b.data[b.data.len() - 8..]);
+        }
+    }
+}
+
+fn av1_config() -> EncoderConfig {
+    let mut config = config();
+    config.codec = Codec::Av1;
+    config
+}
+
+#[test]
+fn av1_temporal_units_are_obus() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&av1_config(), backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    assert_eq!(packets.len(), 3);
+    // temporal delimiter, then a sequence header or a frame
+    assert_eq!(&packets[0].data[..3], &[0x12, 0x00, 0x0a]);
+    assert_eq!(&packets[1].data[..3], &[0x12, 0x00, 0x32]);
+}
+
+#[test]
+fn av1_tiles_are_limited_by_superblocks() {
+    let backend = Arc::new(MockBackend::new());
+    // 64x48 is a single superblock
+    let mut config = av1_config();
+    config.tile_columns = 1;
+    config.tile_rows = 1;
+    assert!(Encoder::with_backend(&config, backend.clone()).is_ok());
+
+    config.tile_columns = 2;
+    assert!(Encoder::with_backend(&config, backend.clone()).is_err());
+    assert_eq!(backend.open_sessions(), 0);
+}
+
+#[test]
+fn tiles_are_rejected_for_other_codecs() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = config();
+    config.tile_columns = 2;
+
+    match Encoder::with_backend(&config, backend) {
+        Err(Error::Config(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("H.264 tiles accepted"),
+    }
+}
+
+#[test]
+fn legacy_runtime_has_no_av1() {
+    let backend = Arc::new(MockBackend::new().legacy());
+    assert!(Encoder::with_backend(&av1_config(), backend).is_err());
+}
+
+fn vp9_config() -> EncoderConfig {
+    let mut config = config();
+    config.codec = Codec::Vp9;
+    config
+}
+
+#[test]
+fn vp9_frames_come_without_ivf_headers() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&vp9_config(), backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 3);
+    assert_eq!(packets.len(), 3);
+    // key frame with its sync code, then inter frames
+    assert_eq!(&packets[0].data[..4], &[0x82, 0x49, 0x83, 0x42]);
+    assert_eq!(packets[1].data[0], 0x86);
+}
+
+#[test]
+fn vp9_tile_columns_are_at_least_256_pixels_wide() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = vp9_config();
+    config.width = 512;
+    config.tile_columns = 2;
+    config.tile_rows = 4;
+    assert!(Encoder::with_backend(&config, backend.clone()).is_ok());
+
+    config.tile_columns = 4;
+    assert!(Encoder::with_backend(&config, backend).is_err());
+}
+
+#[test]
+fn vp9_tile_columns_must_be_a_power_of_two() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = vp9_config();
+    config.tile_columns = 3;
+
+    match Encoder::with_backend(&config, backend) {
+        Err(Error::Config(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("3 tile columns accepted"),
+    }
+}
+
+#[test]
+fn vp9_segmentation_map_is_checked() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = vp9_config();
+    // 64x48 in 16x16 blocks, right half in a lower quality segment
+    let segment_ids = (0..4 * 3).map(|i| (i % 4 >= 2) as u8).collect();
+    config.vp9_segmentation = Some(Vp9Segmentation {
+        segments: vec![
+            Vp9Segment::default(),
+            Vp9Segment {
+                qindex_delta: 20,
+                loop_filter_level_delta: -4,
+            },
+        ],
+        block_size: 16,
+        segment_ids,
+    });
+    let mut encoder = Encoder::with_backend(&config, backend.clone()).unwrap();
+    assert_eq!(encode_all(&mut encoder, 2).len(), 2);
+
+    // a missing segment, then a map too short for the frame
+    let mut missing = config.clone();
+    missing.vp9_segmentation.as_mut().unwrap().segment_ids[0] = 2;
+    assert!(Encoder::with_backend(&missing, backend.clone()).is_err());
+    let mut short = config.clone();
+    short.vp9_segmentation.as_mut().unwrap().segment_ids.pop();
+    assert!(Encoder::with_backend(&short, backend).is_err());
+}
+
+#[test]
+fn vp9_segmentation_map_covers_the_aligned_frame() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = vp9_config();
+    // 100x48 is encoded as 112x48, 14x6 blocks of 8x8 rather than 13x6
+    config.width = 100;
+    config.vp9_segmentation = Some(Vp9Segmentation {
+        segments: vec![Vp9Segment::default()],
+        block_size: 8,
+        segment_ids: vec![0; 13 * 6],
+    });
+    assert!(Encoder::with_backend(&config, backend.clone()).is_err());
+
+    config.vp9_segmentation.as_mut().unwrap().segment_ids = vec![0; 14 * 6];
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+    let frame = vec![0x80; 100 * 48 * 3 / 2];
+    let mut packets = encoder.encode(&frame).unwrap();
+    packets.extend(encoder.flush().unwrap());
+    assert_eq!(packets.len(), 1);
+}
+
+fn jpeg_config(quality: u16) -> EncoderConfig {
+    let mut config = config();
+    config.codec = Codec::Jpeg;
+    config.jpeg_quality = quality;
+    config
+}
+
+#[test]
+fn jpeg_frames_are_standalone_images() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&jpeg_config(50), backend).unwrap();
+
+    for n in 0..3 {
+        let image = encoder.encode_image(&frame(n)).unwrap();
+        let data = &image.data;
+        assert_eq!(&data[..2], &[0xff, 0xd8]);
+        assert_eq!(&data[data.len() - 2..], &[0xff, 0xd9]);
+        // quantization table segment, then the frame header with the size
+        assert_eq!(&data[2..7], &[0xff, 0xdb, 0, 3, 50]);
+        assert_eq!(&data[7..9], &[0xff, 0xc0]);
+        assert_eq!(&data[12..16], &[0, HEIGHT as u8, 0, WIDTH as u8]);
+    }
+}
+
+#[test]
+fn images_wait_for_frames_in_flight() {
+    let backend = Arc::new(MockBackend::new());
+    let mut config = jpeg_config(50);
+    config.async_depth = 2;
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    assert!(encoder.encode(&frame(0)).unwrap().is_empty());
+    match encoder.encode_image(&frame(1)) {
+        Err(Error::Config(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(encoder.flush().unwrap().len(), 1);
+}
+
+#[test]
+fn jpeg_quality_is_validated() {
+    let backend = Arc::new(MockBackend::new());
+    for &quality in &[0, 101] {
+        match Encoder::with_backend(&jpeg_config(quality), backend.clone()) {
+            Err(Error::Config(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("quality {} accepted", quality),
+        }
+    }
+}
+
+#[test]
+fn only_jpeg_encodes_images() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config(), backend).unwrap();
+
+    match encoder.encode_image(&frame(0)) {
+        Err(Error::Config(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn every_rate_control_method_encodes_h264() {
+    let methods = [
+        RateControl::Cbr,
+        RateControl::Vbr,
+        RateControl::Cqp {
+            qpi: 22,
+            qpp: 24,
+            qpb: 26,
+        },
+        RateControl::Avbr {
+            accuracy: 10,
+            convergence: 10,
+        },
+        RateControl::Icq { quality: 23 },
+        RateControl::La { depth: 40 },
+        RateControl::LaIcq {
+            quality: 23,
+            depth: 0,
+        },
+        RateControl::Qvbr { quality: 23 },
+    ];
+    for &rate_control in &methods {
+        let mut config = config();
+        config.rate_control = rate_control;
+        let backend = Arc::new(MockBackend::new());
+        let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+        assert_eq!(encode_all(&mut encoder, 2).len(), 2, "{:?}", rate_control);
+    }
+}
+
+#[test]
+fn constant_quality_needs_no_bitrate() {
+    let mut config = EncoderConfig::new(WIDTH, HEIGHT, 0);
+    assert!(config.validate().is_err());
+    config.rate_control = "icq".parse().unwrap();
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn invalid_rate_control_combinations_are_rejected() {
+    let cases = [
+        // look-ahead and AVBR are H.264 only
+        (Codec::Hevc, RateControl::La { depth: 0 }),
+        (Codec::Vp9, "avbr".parse().unwrap()),
+        (Codec::Av1, RateControl::Icq { quality: 23 }),
+        (Codec::Avc, RateControl::La { depth: 5 }),
+        (
+            Codec::Avc,
+            RateControl::LaIcq {
+                quality: 23,
+                depth: 101,
+            },
+        ),
+        (Codec::Avc, RateControl::Icq { quality: 0 }),
+        (Codec::Hevc, RateControl::Qvbr { quality: 52 }),
+        (
+            Codec::Avc,
+            RateControl::Cqp {
+                qpi: 20,
+                qpp: 52,
+                qpb: 30,
+            },
+        ),
+        (
+            Codec::Avc,
+            RateControl::Avbr {
+                accuracy: 0,
+                convergence: 10,
+            },
+        ),
+    ];
+    for &(codec, rate_control) in &cases {
+        let mut config = config();
+        config.codec = codec;
+        config.rate_control = rate_control;
+        match Encoder::with_backend(&config, Arc::new(MockBackend::new())) {
+            Err(Error::Config(_)) => {}
+            Err(e) => panic!("{:?} {:?}: unexpected error {:?}", codec, rate_control, e),
+            Ok(_) => panic!("{:?} {:?} was accepted", codec, rate_control),
+        }
+    }
+}
+
+#[test]
+fn vp9_quantizers_go_up_to_255() {
+    let mut config = vp9_config();
+    config.rate_control = RateControl::Cqp {
+        qpi: 100,
+        qpp: 180,
+        qpb: 255,
+    };
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+    assert_eq!(encode_all(&mut encoder, 2).len(), 2);
+}
+
+#[test]
+fn bitrates_above_16_bits_are_scaled() {
+    let mut config = config();
+    config.bitrate = 120_000;
+    config.max_bitrate = 180_000;
+    config.buffer_size = 90_000;
+    config.initial_delay = 45_000;
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    assert_eq!(
+        encoder.bitrate_params().unwrap(),
+        BitrateParams {
+            target_kbps: 120_000,
+            max_kbps: 180_000,
+            buffer_size_kb: 90_000,
+            initial_delay_kb: 45_000,
+        }
+    );
+    assert_eq!(encode_all(&mut encoder, 2).len(), 2);
+}
+
+#[test]
+fn runtime_buffer_size_is_unscaled() {
+    let mut config = config();
+    config.bitrate = 200_000;
+    let backend = Arc::new(MockBackend::new());
+    let encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    // the mock picks the 4.6 KB of a raw frame, rounded up to units of the
+    // multiplier of 4
+    let params = encoder.bitrate_params().unwrap();
+    assert_eq!(params.target_kbps, 200_000);
+    assert_eq!(params.buffer_size_kb, 8);
+}
+
+#[test]
+fn inconsistent_bitrates_are_rejected() {
+    let mut below = config();
+    below.max_bitrate = 400;
+    let mut cqp = config();
+    cqp.rate_control = "cqp".parse().unwrap();
+    cqp.initial_delay = 100;
+    let mut delay = config();
+    delay.buffer_size = 100;
+    delay.initial_delay = 200;
+    for config in &[below, cqp, delay] {
+        match Encoder::with_backend(config, Arc::new(MockBackend::new())) {
+            Err(Error::Config(_)) => {}
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("{:?} was accepted", config),
+        }
+    }
+}
+
+// I and IDR frame numbers among `packets`.
+fn keyframes(packets: &[Packet]) -> (Vec<usize>, Vec<usize>) {
+    let numbers = |flag| {
+        (packets.iter().enumerate())
+            .filter(|(_, p)| p.frame_type & flag != 0)
+            .map(|(n, _)| n)
+            .collect()
+    };
+    (numbers(MFX_FRAMETYPE_I), numbers(MFX_FRAMETYPE_IDR))
+}
+
+#[test]
+fn keyframe_interval_in_seconds_sets_the_gop() {
+    let mut config = config();
+    config.frame_rate = FrameRate::new(10, 1);
+    config.keyframe_interval = Some("0.5s".parse().unwrap());
+    config.idr_interval = Some(2);
+    config.b_frames = Some(2);
+    config.closed_gop = true;
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 16);
+    let (i_frames, idr_frames) = keyframes(&packets);
+    assert_eq!(i_frames, [0, 5, 10, 15]);
+    assert_eq!(idr_frames, [0, 10]);
+    // parameter sets come with each IDR frame
+    for &n in &idr_frames {
+        assert_eq!(packets[n].data[4], 0x67);
+    }
+    assert_eq!(packets[5].data[4], 0x41);
+}
+
+#[test]
+fn hevc_idr_interval_counts_i_frames() {
+    let mut config = hevc_config(None);
+    config.keyframe_interval = Some(KeyframeInterval::Frames(4));
+    config.idr_interval = Some(1);
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let packets = encode_all(&mut encoder, 9);
+    let (i_frames, idr_frames) = keyframes(&packets);
+    assert_eq!(i_frames, [0, 4, 8]);
+    assert_eq!(idr_frames, i_frames);
+    for &n in &idr_frames {
+        // VPS
+        assert_eq!(packets[n].data[4], 0x40);
+    }
+}
+
+#[test]
+fn gop_settings_are_validated() {
+    let mut vp9 = vp9_config();
+    vp9.b_frames = Some(1);
+    let mut too_many_b_frames = config();
+    too_many_b_frames.keyframe_interval = Some(KeyframeInterval::Frames(3));
+    too_many_b_frames.b_frames = Some(3);
+    let mut empty_gop = config();
+    empty_gop.keyframe_interval = Some("0.01s".parse().unwrap());
+    let mut no_idr = config();
+    no_idr.idr_interval = Some(0);
+    let mut no_frame_rate = config();
+    no_frame_rate.frame_rate = FrameRate::new(30, 0);
+    no_frame_rate.keyframe_interval = Some(KeyframeInterval::Time(Duration::from_secs(2)));
+    for config in &[vp9, too_many_b_frames, empty_gop, no_idr, no_frame_rate] {
+        match config.validate() {
+            Err(Error::Config(_)) => {}
+            other => panic!("{:?}: unexpected result {:?}", config, other),
+        }
+    }
+
+    assert_eq!(
+        "2s".parse::<KeyframeInterval>().unwrap(),
+        KeyframeInterval::Time(Duration::from_secs(2))
+    );
+    assert_eq!(
+        "60".parse::<KeyframeInterval>().unwrap(),
+        KeyframeInterval::Frames(60)
+    );
+    assert!("-1s".parse::<KeyframeInterval>().is_err());
+}
+
+// profile_idc, constraint flags and level_idc of the first SPS
+fn avc_profile_and_level(config: &EncoderConfig) -> [u8; 3] {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(config, backend).unwrap();
+    let packets = encode_all(&mut encoder, 1);
+    let sps = first_nal_payload(&packets[0].data);
+    [sps[0], sps[1], sps[2]]
+}
+
+#[test]
+fn h264_level_is_the_lowest_that_fits() {
+    let mut config = config();
+    config.profile = Some(Profile::ConstrainedBaseline);
+    // 768 kbps at level 1.3
+    assert_eq!(avc_profile_and_level(&config), [66, 0x40, 13]);
+
+    // 5000 kbps fits level 2.1 only with the 1.25 factor of High
+    config.bitrate = 5000;
+    config.profile = Some(Profile::Main);
+    assert_eq!(avc_profile_and_level(&config), [77, 0, 30]);
+    config.profile = Some(Profile::High);
+    assert_eq!(avc_profile_and_level(&config), [100, 0, 21]);
+
+    config.level = Some("4.0".parse().unwrap());
+    assert_eq!(avc_profile_and_level(&config), [100, 0, 40]);
+}
+
+#[test]
+fn exceeding_the_h264_level_is_rejected() {
+    let mut config = EncoderConfig::new(1920, 1080, 6000);
+    config.frame_rate = FrameRate::new(60, 1);
+    config.level = Some("4.2".parse().unwrap());
+    assert!(config.validate().is_ok());
+
+    config.level = Some("4.1".parse().unwrap());
+    match config.validate() {
+        Err(Error::Config(message)) => {
+            assert_eq!(message, "1920x1080 at 60/1 fps exceeds level 4.1")
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let mut bitrate = config.clone();
+    bitrate.frame_rate = FrameRate::new(30, 1);
+    bitrate.bitrate = 60_000;
+    let mut no_level = EncoderConfig::new(8192, 4320, 6000);
+    no_level.frame_rate = FrameRate::new(240, 1);
+    let mut hevc = hevc_config(None);
+    hevc.level = Some("4".parse().unwrap());
+    let mut baseline_b_frames = self::config();
+    baseline_b_frames.profile = Some(Profile::Baseline);
+    baseline_b_frames.b_frames = Some(2);
+    for config in &[bitrate, no_level, hevc, baseline_b_frames] {
+        match config.validate() {
+            Err(Error::Config(_)) => {}
+            other => panic!("{:?}: unexpected result {:?}", config, other),
+        }
+    }
+
+    assert_eq!("1b".parse::<Level>().unwrap().to_string(), "1b");
+    assert!("4.4".parse::<Level>().is_err());
+}
+
+#[test]
+fn h264_high10_is_left_to_the_runtime() {
+    let mut config = config();
+    config.profile = Some(Profile::High10);
+    assert!(config.validate().is_ok());
+    // the mock, like Quick Sync hardware, has no 10-bit H.264 encoder
+    match Encoder::with_backend(&config, Arc::new(MockBackend::new())) {
+        Err(e) => assert_eq!(e.mfx_error(), Some(MfxError::Unsupported)),
+        Ok(_) => panic!("High 10 was accepted"),
+    }
+}
+
+#[test]
+fn coding_options_are_read_back_resolved() {
+    let mut config = config();
+    config.rate_control = RateControl::La { depth: 40 };
+    config.coding_option.cavlc = Some(true);
+    config.coding_option2.max_slice_size = Some(1500);
+    config.coding_option2.trellis = Some(Trellis {
+        i: true,
+        p: true,
+        b: false,
+    });
+    config.coding_option3.weighted_pred = Some(WeightedPrediction::Explicit);
+    let encoder = Encoder::with_backend(&config, Arc::new(MockBackend::new())).unwrap();
+
+    let (co, co2, co3) = encoder.coding_options().unwrap();
+    assert_eq!(co.cavlc, Some(true));
+    assert_eq!(co.aud, Some(false));
+    assert_eq!(co.nal_hrd_conformance, Some(true));
+    assert_eq!(co2.max_slice_size, Some(1500));
+    assert_eq!(co2.max_frame_size, None);
+    assert_eq!(
+        co2.trellis,
+        Some(Trellis {
+            i: true,
+            p: true,
+            b: false
+        })
+    );
+    assert_eq!(co2.b_pyramid, Some(false));
+    assert_eq!(co3.weighted_pred, Some(WeightedPrediction::Explicit));
+    assert_eq!(co3.weighted_bipred, Some(WeightedPrediction::Default));
+    assert_eq!(co3.gpb, None);
+
+    let hevc = Encoder::with_backend(&hevc_config(None), Arc::new(MockBackend::new())).unwrap();
+    let (co, _, co3) = hevc.coding_options().unwrap();
+    assert_eq!(co.cavlc, None);
+    assert_eq!(co3.gpb, Some(true));
+}
+
+#[test]
+fn aud_starts_every_access_unit() {
+    for mut config in [config(), hevc_config(None)] {
+        config.coding_option.aud = Some(true);
+        let mut encoder = Encoder::with_backend(&config, Arc::new(MockBackend::new())).unwrap();
+
+        for packet in encode_all(&mut encoder, 3) {
+            let nal_type = match config.codec {
+                Codec::Hevc => packet.data[4] >> 1,
+                _ => packet.data[4] & 0x1f,
+            };
+            assert_eq!(nal_type, if config.codec == Codec::Hevc { 35 } else { 9 });
+        }
+    }
+}
+
+#[test]
+fn invalid_coding_options_are_rejected() {
+    let mut vp9 = config();
+    vp9.codec = Codec::Vp9;
+    vp9.coding_option.aud = Some(true);
+    let mut hevc_cavlc = hevc_config(None);
+    hevc_cavlc.coding_option.cavlc = Some(true);
+    let mut baseline_cabac = config();
+    baseline_cabac.profile = Some(Profile::Baseline);
+    baseline_cabac.coding_option.cavlc = Some(false);
+    let mut avc_gpb = config();
+    avc_gpb.coding_option3.gpb = Some(false);
+    let mut implicit_p = config();
+    implicit_p.coding_option3.weighted_pred = Some(WeightedPrediction::Implicit);
+    let mut strict_adaptive_i = config();
+    strict_adaptive_i.strict_gop = true;
+    strict_adaptive_i.coding_option2.adaptive_i = Some(true);
+    let mut pyramid_without_b = config();
+    pyramid_without_b.b_frames = Some(0);
+    pyramid_without_b.coding_option2.b_pyramid = Some(true);
+    for config in &[
+        vp9,
+        hevc_cavlc,
+        baseline_cabac,
+        avc_gpb,
+        implicit_p,
+        strict_adaptive_i,
+        pyramid_without_b,
+    ] {
+        match config.validate() {
+            Err(Error::Config(_)) => {}
+            other => panic!("{:?}: unexpected result {:?}", config, other),
+        }
+    }
+
+    // nothing to read back without the buffers
+    let mut jpeg = config();
+    jpeg.codec = Codec::Jpeg;
+    let encoder = Encoder::with_backend(&jpeg, Arc::new(MockBackend::new())).unwrap();
+    assert!(encoder.coding_options().is_err());
+}
+
+fn encode_with_controls(encoder: &mut Encoder, controls: &[FrameControl]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    for (n, control) in controls.iter().enumerate() {
+        packets.extend(encoder.encode_with_control(&frame(n), control).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    packets
+}
+
+#[test]
+fn frame_control_forces_i_and_idr_frames() {
+    let force = |force| FrameControl {
+        force: Some(force),
+        ..FrameControl::default()
+    };
+    let none = FrameControl::default();
+    let controls = [
+        none,
+        none,
+        force(ForcedFrame::Idr),
+        none,
+        force(ForcedFrame::I),
+    ];
+    let mut encoder = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+
+    let packets = encode_with_controls(&mut encoder, &controls);
+    assert_eq!(keyframes(&packets), (vec![0, 2, 4], vec![0, 2]));
+    // the forced IDR frame repeats the parameter sets
+    assert_eq!(packets[2].data[4] & 0x1f, 7);
+    assert_eq!(packets[4].data[4] & 0x1f, 1);
+}
+
+#[test]
+fn frame_control_skips_p_frames() {
+    let skip = FrameControl {
+        skip: true,
+        ..FrameControl::default()
+    };
+    let controls = [skip, FrameControl::default(), skip];
+    let mut config = config();
+
+    config.coding_option2.skip_frame = Some(SkipFrame::InsertNothing);
+    let mut encoder = Encoder::with_backend(&config, Arc::new(MockBackend::new())).unwrap();
+    let packets = encode_with_controls(&mut encoder, &controls);
+    // the first frame is an IDR frame, which is never skipped
+    assert!(!packets[0].data.is_empty());
+    assert!(packets[2].data.is_empty());
+    assert_eq!(packets[2].timestamp, 6000);
+
+    config.coding_option2.skip_frame = Some(SkipFrame::InsertDummy);
+    let mut encoder = Encoder::with_backend(&config, Arc::new(MockBackend::new())).unwrap();
+    let packets = encode_with_controls(&mut encoder, &controls);
+    assert_eq!(first_nal_payload(&packets[1].data).len(), 8);
+    assert_eq!(first_nal_payload(&packets[2].data), [0, 0, 0, 2]);
+}
+
+#[test]
+fn frame_control_is_validated() {
+    let qp = |qp| FrameControl {
+        qp: Some(qp),
+        ..FrameControl::default()
+    };
+    let mut cqp = config();
+    cqp.rate_control = RateControl::Cqp {
+        qpi: 26,
+        qpp: 28,
+        qpb: 30,
+    };
+    let mut encoder = Encoder::with_backend(&cqp, Arc::new(MockBackend::new())).unwrap();
+    assert!(encoder.encode_with_control(&frame(0), &qp(20)).is_ok());
+    assert!(encoder.encode_with_control(&frame(1), &qp(52)).is_err());
+
+    let mut vbr = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+    let skip_and_force = FrameControl {
+        force: Some(ForcedFrame::I),
+        skip: true,
+        qp: None,
+    };
+    let skip = FrameControl {
+        skip: true,
+        ..FrameControl::default()
+    };
+    for control in &[qp(20), skip, skip_and_force] {
+        match vbr.encode_with_control(&frame(0), control) {
+            Err(Error::Config(_)) => {}
+            other => panic!("{:?}: unexpected result {:?}", control, other),
+        }
+    }
+
+    let mut jpeg = config();
+    jpeg.codec = Codec::Jpeg;
+    let mut encoder = Encoder::with_backend(&jpeg, Arc::new(MockBackend::new())).unwrap();
+    let idr = FrameControl {
+        force: Some(ForcedFrame::Idr),
+        ..FrameControl::default()
+    };
+    assert!(encoder.encode_with_control(&frame(0), &idr).is_err());
+}
+
+#[test]
+fn reconfigure_changes_the_bitrate_in_place() {
+    let mut encoder = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+    let mut packets = Vec::new();
+    for n in 0..3 {
+        packets.extend(encoder.encode(&frame(n)).unwrap());
+    }
+
+    let mut config = config();
+    config.bitrate = 1000;
+    // the buffered frames have to come out first
+    match encoder.reconfigure(&config, false) {
+        Err(Error::Config(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    packets.extend(encoder.flush().unwrap());
+    assert_eq!(packets.len(), 3);
+    assert!(encoder.reconfigure(&config, false).unwrap().in_place);
+    assert_eq!(encoder.bitrate_params().unwrap().target_kbps, 1000);
+
+    let packets = encode_all(&mut encoder, 2);
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].timestamp, 9000);
+    assert_eq!(packets[0].frame_type & MFX_FRAMETYPE_I, 0);
+
+    encoder.reconfigure(&config, true).unwrap();
+    let packets = encode_all(&mut encoder, 1);
+    assert_ne!(packets[0].frame_type & MFX_FRAMETYPE_IDR, 0);
+}
+
+#[test]
+fn reconfigure_changes_frame_size_and_rate() {
+    let mut encoder = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+    encode_all(&mut encoder, 3);
+
+    let mut small = EncoderConfig::new(32, 32, 500);
+    small.frame_rate = FrameRate::new(15, 1);
+    assert!(encoder.reconfigure(&small, false).unwrap().in_place);
+    assert_eq!(encoder.frame_size(), 32 * 32 * 3 / 2);
+
+    let frames: Vec<Vec<u8>> = (0..2).map(|n| vec![n as u8; 32 * 32 * 3 / 2]).collect();
+    let mut packets = Vec::new();
+    for frame in &frames {
+        packets.extend(encoder.encode(frame).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    // a new frame size starts a new sequence, with its own SPS
+    assert_ne!(packets[0].frame_type & MFX_FRAMETYPE_IDR, 0);
+    assert_eq!(&first_nal_payload(&packets[0].data)[3..7], &[0, 32, 0, 32]);
+    assert_eq!(packets[0].timestamp, 9000);
+    assert_eq!(packets[1].timestamp, 15000);
+}
+
+#[test]
+fn reconfigure_reinitializes_what_reset_cannot_change() {
+    let mut encoder = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+    encode_all(&mut encoder, 3);
+
+    let mut cbr = config();
+    cbr.rate_control = RateControl::Cbr;
+    assert!(!encoder.reconfigure(&cbr, false).unwrap().in_place);
+    let packets = encode_all(&mut encoder, 2);
+    assert_eq!(packets.len(), 2);
+    assert_ne!(packets[0].frame_type & MFX_FRAMETYPE_IDR, 0);
+}
+
+#[test]
+fn reconfigure_rejects_what_the_surfaces_cannot_take() {
+    let mut encoder = Encoder::with_backend(&config(), Arc::new(MockBackend::new())).unwrap();
+    encoder.encode(&frame(0)).unwrap();
+
+    let larger = EncoderConfig::new(WIDTH * 2, HEIGHT, 500);
+    let mut hevc = hevc_config(None);
+    hevc.bitrate = 1000;
+    let mut nv12 = config();
+    nv12.input_format = InputFormat::Nv12;
+    for config in &[larger, hevc, nv12] {
+        match encoder.reconfigure(config, false) {
+            Err(Error::Config(_)) => {}
+            other => panic!("{:?}: unexpected result {:?}", config, other),
+        }
+    }
+
+    // nothing was drained or reset
+    let mut packets = Vec::new();
+    for n in 1..3 {
+        packets.extend(encoder.encode(&frame(n)).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    assert_eq!(packets.len(), 3);
+}
+
+#[test]
+fn failed_reconfiguration_leaves_the_encoder_unusable() {
+    let backend = Arc::new(MockBackend::new().with_failing_encode_reset());
+    let mut encoder = Encoder::with_backend(&config(), backend).unwrap();
+    assert_eq!(encode_all(&mut encoder, 3).len(), 3);
+
+    // VPP takes the new frame rate, ENCODE fails to
+    let mut config = config();
+    config.frame_rate = FrameRate::new(15, 1);
+    match encoder.reconfigure(&config, false) {
+        Err(Error::Mfx { error, .. }) => assert_eq!(error, MfxError::DeviceFailed),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    for result in [encoder.encode(&frame(3)), encoder.flush()] {
+        match result {
+            Err(Error::Config(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+    assert!(encoder.reconfigure(&config, false).is_err());
+}
+
+#[test]
+fn wrong_frame_size_is_rejected() {
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config(), backend).unwrap();
+
+    match encoder.encode(&[0; 16]) {
+        Err(Error::InvalidFrame { actual: 16, .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn implementations_without_a_session_are_skipped() {
+    let backend = Arc::new(MockBackend::new().with_broken_implementation());
+    let implementations = query_capabilities_with_backend(backend.clone()).unwrap();
+    assert_eq!(backend.open_sessions(), 0);
+    assert_eq!(backend.open_loaders(), 0);
+
+    assert_eq!(implementations.len(), 1);
+    assert_eq!(implementations[0].name, "mock");
+}
+
+#[test]
+fn capabilities_are_queried_per_implementation() {
+    let backend = Arc::new(MockBackend::new());
+    let implementations = query_capabilities_with_backend(backend.clone()).unwrap();
+    assert_eq!(backend.open_sessions(), 0);
+    assert_eq!(backend.open_loaders(), 0);
+
+    assert_eq!(implementations.len(), 1);
+    let imp = &implementations[0];
+    assert_eq!(imp.name, "mock");
+    assert_eq!(imp.api_version, (2, 9));
+    assert_eq!(imp.encoders.len(), 5);
+
+    let avc = &imp.encoders[0];
+    assert_eq!(avc.codec, "H.264");
+    assert_eq!(
+        avc.profiles,
+        ["Constrained Baseline", "Baseline", "Main", "High"]
+    );
+    assert_eq!((avc.max_width, avc.max_height), (4096, 4096));
+    assert_eq!(
+        avc.rate_control_methods,
+        ["CBR", "VBR", "CQP", "AVBR", "LA", "ICQ", "LA_ICQ", "QVBR"]
+    );
+    assert!(!avc.low_power);
+    assert_eq!(avc.input_fourccs, ["NV12"]);
+
+    let hevc = &imp.encoders[1];
+    assert_eq!(hevc.codec, "HEVC");
+    assert_eq!(hevc.profiles, ["Main", "Main 10", "Range Extensions"]);
+    assert_eq!((hevc.max_width, hevc.max_height), (8192, 8192));
+    assert_eq!(
+        hevc.rate_control_methods,
+        ["CBR", "VBR", "CQP", "ICQ", "QVBR"]
+    );
+    assert!(hevc.low_power);
+    assert_eq!(hevc.input_fourccs, ["NV12", "P010", "AYUV"]);
+
+    let vp9 = &imp.encoders[2];
+    assert_eq!(vp9.codec, "VP9");
+    assert_eq!(
+        vp9.profiles,
+        ["Profile 0", "Profile 1", "Profile 2", "Profile 3"]
+    );
+    assert!(vp9.low_power);
+
+    let av1 = &imp.encoders[3];
+    assert_eq!(av1.codec, "AV1");
+    assert_eq!(av1.profiles, ["Main", "High"]);
+    assert_eq!((av1.max_width, av1.max_height), (8192, 8192));
+    assert!(av1.low_power);
+
+    let jpeg = &imp.encoders[4];
+    assert_eq!(jpeg.codec, "JPEG");
+    assert_eq!(jpeg.profiles, ["Baseline"]);
+    assert_eq!((jpeg.max_width, jpeg.max_height), (16384, 16384));
+    assert!(jpeg.rate_control_methods.is_empty());
+    assert!(!jpeg.low_power);
+    assert_eq!(jpeg.input_fourccs, ["NV12", "YUY2", "RGB4"]);
+}
+
+#[test]
+fn legacy_capabilities_probe_each_adapter() {
+    let backend = Arc::new(MockBackend::new().legacy());
+    let implementations = query_capabilities_with_backend(backend).unwrap();
+
+    assert_eq!(implementations.len(), 1);
+    assert_eq!(implementations[0].name, "hardware");
+    assert_eq!(implementations[0].api_version, (1, 35));
+    // HEVC through its plugin, no AV1
+    let codecs: Vec<&str> = implementations[0]
+        .encoders
+        .iter()
+        .map(|e| e.codec)
+        .collect();
+    assert_eq!(codecs, ["H.264", "HEVC", "VP9", "JPEG"]);
+}
@@ -0,0 +1,43 @@
+extern crate qsv_rust;
+
+use std::sync::Arc;
+
+use qsv_rust::{Encoder, EncoderConfig, Error, MfxError, SdkBackend};
+
+#[test]
+fn missing_runtime_is_reported() {
+    match SdkBackend::open("/nonexistent/libmfx.so.1") {
+        Err(Error::RuntimeNotFound(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("runtime loaded from a nonexistent path"),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn stub_runtime_is_called_through_the_loader() {
+    use std::env;
+    use std::path::Path;
+    use std::process::Command;
+
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let stub = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libmfx_stub.so");
+    let status = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+        .args(["--crate-type", "cdylib", "--edition", "2018", "-o"])
+        .arg(&stub)
+        .arg(manifest_dir.join("tests/stub/mfx_stub.rs"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let backend = SdkBackend::open(stub.to_str().unwrap()).unwrap();
+    let config = EncoderConfig::new(64, 48, 500);
+    match Encoder::with_backend(&config, Arc::new(backend)) {
+        Err(Error::Mfx { function, error }) => {
+            assert_eq!(function, "MFXVideoVPP_QueryIOSurf");
+            assert_eq!(error, MfxError::Unsupported);
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("stub runtime initialized an encoder"),
+    }
+}
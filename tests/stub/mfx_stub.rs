@@ -0,0 +1,69 @@
+// Minimal Media SDK runtime for the loader tests: sessions open and close,
+// every component call fails with MFX_ERR_UNSUPPORTED.
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use std::os::raw::c_void;
+
+const MFX_ERR_NONE: i32 = 0;
+const MFX_ERR_UNSUPPORTED: i32 = -3;
+const MFX_IMPL_HARDWARE: i32 = 0x0002;
+
+#[no_mangle]
+pub unsafe extern "system" fn MFXInit(
+    _impl: i32,
+    _ver: *const c_void,
+    session: *mut *mut c_void,
+) -> i32 {
+    *session = 1 as *mut c_void;
+    MFX_ERR_NONE
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn MFXClose(_session: *mut c_void) -> i32 {
+    MFX_ERR_NONE
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn MFXQueryIMPL(
+    _session: *const c_void,
+    implementation: *mut i32,
+) -> i32 {
+    *implementation = MFX_IMPL_HARDWARE;
+    MFX_ERR_NONE
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn MFXQueryVersion(_session: *const c_void, version: *mut u16) -> i32 {
+    // mfxVersion { Minor, Major }
+    *version = 35;
+    *version.add(1) = 1;
+    MFX_ERR_NONE
+}
+
+macro_rules! unsupported {
+    ($($name:ident($($arg:ty),*);)*) => {
+        $(
+            #[no_mangle]
+            pub unsafe extern "system" fn $name($(_: $arg),*) -> i32 {
+                MFX_ERR_UNSUPPORTED
+            }
+        )*
+    };
+}
+
+unsupported! {
+    MFXVideoENCODE_Query(*const c_void, *const c_void, *mut c_void);
+    MFXVideoENCODE_QueryIOSurf(*const c_void, *const c_void, *mut c_void);
+    MFXVideoENCODE_Init(*const c_void, *const c_void);
+    MFXVideoENCODE_GetVideoParam(*const c_void, *mut c_void);
+    MFXVideoENCODE_EncodeFrameAsync(*const c_void, *const c_void, *const c_void, *mut c_void, *mut c_void);
+    MFXVideoCORE_SyncOperation(*const c_void, *const c_void, u32);
+    MFXVideoENCODE_Reset(*const c_void, *const c_void);
+    MFXVideoENCODE_Close(*const c_void);
+    MFXVideoVPP_QueryIOSurf(*const c_void, *const c_void, *mut c_void);
+    MFXVideoVPP_Init(*const c_void, *mut c_void);
+    MFXVideoVPP_RunFrameVPPAsync(*const c_void, *const c_void, *mut c_void, *const c_void, *mut c_void);
+    MFXVideoVPP_Reset(*const c_void, *mut c_void);
+    MFXVideoVPP_Close(*const c_void);
+}
@@ -0,0 +1,118 @@
+extern crate qsv_rust;
+
+use std::sync::Arc;
+
+use qsv_rust::{Codec, Encoder, EncoderConfig, MockBackend, Packet, WebmWriter};
+
+const SEGMENT: u64 = 0x1853_8067;
+const TRACKS: u64 = 0x1654_ae6b;
+const CLUSTER: u64 = 0x1f43_b675;
+const TIMECODE: u64 = 0xe7;
+const SIMPLE_BLOCK: u64 = 0xa3;
+
+// Reads a variable length integer, keeping the length marker for IDs.
+fn vint(data: &[u8], offset: &mut usize, keep_marker: bool) -> Option<u64> {
+    let first = data[*offset];
+    let length = first.leading_zeros() as usize + 1;
+    let mask = 0xff_u64 >> length;
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        first as u64 & mask
+    };
+    let mut all_ones = value == mask;
+    for &b in &data[*offset + 1..*offset + length] {
+        value = value << 8 | b as u64;
+        all_ones &= b == 0xff;
+    }
+    *offset += length;
+    if !keep_marker && all_ones {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// ID, body range of each element in `data[start..end]`; unknown sizes run to
+// `end`.
+fn elements(data: &[u8], start: usize, end: usize) -> Vec<(u64, usize, usize)> {
+    let mut out = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        let id = vint(data, &mut offset, true).unwrap();
+        let size = vint(data, &mut offset, false);
+        let body_end = size.map_or(end, |size| offset + size as usize);
+        out.push((id, offset, body_end));
+        offset = body_end;
+    }
+    out
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+fn encode_vp9(frames: usize) -> (EncoderConfig, Vec<Packet>) {
+    let mut config = EncoderConfig::new(64, 48, 500);
+    config.codec = Codec::Vp9;
+    let backend = Arc::new(MockBackend::new());
+    let mut encoder = Encoder::with_backend(&config, backend).unwrap();
+
+    let frame = vec![0x80; encoder.frame_size()];
+    let mut packets = Vec::new();
+    for _ in 0..frames {
+        packets.extend(encoder.encode(&frame).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+    (config, packets)
+}
+
+#[test]
+fn vp9_stream_is_written_as_webm() {
+    let (config, packets) = encode_vp9(3);
+    let mut writer = WebmWriter::new(Vec::new(), &config).unwrap();
+    for packet in &packets {
+        writer.write_packet(packet).unwrap();
+    }
+    let file = writer.finish().unwrap();
+
+    let top = elements(&file, 0, file.len());
+    assert_eq!(top.len(), 2);
+    let (_, ebml_start, ebml_end) = top[0];
+    assert!(file[ebml_start..ebml_end].windows(4).any(|w| w == b"webm"));
+
+    let (id, start, end) = top[1];
+    assert_eq!(id, SEGMENT);
+    let children = elements(&file, start, end);
+    let (_, tracks_start, tracks_end) = *children.iter().find(|e| e.0 == TRACKS).unwrap();
+    assert!(file[tracks_start..tracks_end]
+        .windows(5)
+        .any(|w| w == b"V_VP9"));
+
+    // a single cluster, as only the first frame is a key frame
+    let clusters: Vec<_> = children.iter().filter(|e| e.0 == CLUSTER).collect();
+    assert_eq!(clusters.len(), 1);
+    let blocks = elements(&file, clusters[0].1, clusters[0].2);
+    assert_eq!(blocks[0].0, TIMECODE);
+    assert_eq!(uint(&file[blocks[0].1..blocks[0].2]), 0);
+
+    let blocks: Vec<_> = blocks.iter().filter(|e| e.0 == SIMPLE_BLOCK).collect();
+    assert_eq!(blocks.len(), packets.len());
+    for (n, (&&(_, start, end), packet)) in blocks.iter().zip(&packets).enumerate() {
+        let block = &file[start..end];
+        // track 1, 30 fps timecodes in milliseconds, key frame flag
+        assert_eq!(block[0], 0x81);
+        assert_eq!(
+            i16::from_be_bytes([block[1], block[2]]),
+            (n * 100 / 3) as i16
+        );
+        assert_eq!(block[3] & 0x80 != 0, n == 0);
+        assert_eq!(&block[4..], &packet.data[..]);
+    }
+}
+
+#[test]
+fn webm_is_only_written_for_vp9() {
+    let config = EncoderConfig::new(64, 48, 500);
+    assert!(WebmWriter::new(Vec::new(), &config).is_err());
+}